@@ -0,0 +1,327 @@
+//! Matching declared columns to the fields of a CSV record by header name,
+//! for the `match_columns='name'` option, and expanding column selectors
+//! like `*` for xsv_reader.
+
+use csv::StringRecord;
+use regex::Regex;
+use sqlite_loadable::{
+    vtab_argparse::{parse_argument, Argument, ColumnDeclaration},
+    Error, Result,
+};
+
+/// How declared columns are matched to fields in a record.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MatchColumns {
+    /// The i-th declared column is the i-th field. The default.
+    Position,
+    /// Declared columns are matched to fields by header name, per file.
+    Name,
+}
+
+/// What to do when a declared column isn't found in a file's headers.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MissingColumns {
+    Error,
+    Null,
+}
+
+/// Resolves which field index each declared column reads from, given
+/// the header row of a file.
+#[derive(Debug, Clone)]
+pub struct ColumnMatcher {
+    /// Each declared column name, along with its lowercased candidate
+    /// header names: the column name itself first, followed by any aliases.
+    candidates: Vec<(String, Vec<String>)>,
+    missing: MissingColumns,
+}
+
+impl ColumnMatcher {
+    pub fn new(
+        column_names: &[String],
+        aliases: &[(String, Vec<String>)],
+        missing: MissingColumns,
+    ) -> ColumnMatcher {
+        let candidates = column_names
+            .iter()
+            .map(|name| {
+                let mut names = vec![name.to_lowercase()];
+                for (column, column_aliases) in aliases {
+                    if column.eq_ignore_ascii_case(name) {
+                        names.extend(column_aliases.iter().map(|a| a.to_lowercase()));
+                    }
+                }
+                (name.to_owned(), names)
+            })
+            .collect();
+        ColumnMatcher {
+            candidates,
+            missing,
+        }
+    }
+
+    /// For each declared column, the index of the matching field in `headers`,
+    /// or None if the column should be NULL for this file. `source` is only
+    /// used in error messages.
+    pub fn mapping(&self, headers: &StringRecord, source: &str) -> Result<Vec<Option<usize>>> {
+        let headers: Vec<String> = headers.iter().map(|h| h.trim().to_lowercase()).collect();
+        self.candidates
+            .iter()
+            .map(|(column, names)| {
+                let idx = names
+                    .iter()
+                    .find_map(|name| headers.iter().position(|h| h == name));
+                match (idx, self.missing) {
+                    (Some(idx), _) => Ok(Some(idx)),
+                    (None, MissingColumns::Null) => Ok(None),
+                    (None, MissingColumns::Error) => Err(Error::new_message(format!(
+                        "Column '{column}' not found in the headers of {source}"
+                    ))),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The union of all header names across multiple files, in the order they are
+/// first seen. Names are compared case-insensitively, and any header that is an
+/// alias of a column is folded into that column.
+pub fn union_headers<'a>(
+    headers: impl IntoIterator<Item = &'a StringRecord>,
+    aliases: &[(String, Vec<String>)],
+) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for record in headers {
+        for header in record.iter() {
+            let header = header.trim();
+            let name = aliases
+                .iter()
+                .find(|(_, column_aliases)| {
+                    column_aliases
+                        .iter()
+                        .any(|alias| alias.to_lowercase() == header.to_lowercase())
+                })
+                .map_or(header, |(column, _)| column.as_str());
+            if !names
+                .iter()
+                .any(|existing| existing.to_lowercase() == name.to_lowercase())
+            {
+                names.push(name.to_owned());
+            }
+        }
+    }
+    names
+}
+
+/// A column argument of xsv_reader: either a plain column declaration, or a
+/// selector that is expanded against the headers of the `sniff=` sample file.
+pub enum ColumnSpec {
+    Declared(ColumnDeclaration),
+    Selector(ColumnSelector),
+}
+
+pub enum ColumnSelector {
+    /// `* [exclude (a, b)] [replace (c integer, d real)]`: every header, in order.
+    All {
+        exclude: Vec<String>,
+        replace: Vec<ColumnDeclaration>,
+    },
+    /// `columns('regex') [type]`: every header matching the regex.
+    Regex {
+        pattern: Regex,
+        declared_type: Option<String>,
+    },
+}
+
+/// Parses a column selector argument like `* exclude (id)` or `columns('^score_') real`.
+/// Returns None if the argument isn't a selector, so it can be parsed as a regular
+/// column declaration or config option.
+pub fn parse_column_selector(arg: &str) -> Result<Option<ColumnSelector>> {
+    let arg = arg.trim();
+    if let Some(mut rest) = arg.strip_prefix('*') {
+        let mut exclude = vec![];
+        let mut replace = vec![];
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            let (keyword, after) = rest.split_at(rest.find(['(', ' ']).unwrap_or(rest.len()));
+            let (list, after) = parenthesized(after, arg)?;
+            match keyword.to_lowercase().as_str() {
+                "exclude" => exclude.extend(list.into_iter().map(|name| unquote(&name))),
+                "replace" => {
+                    for declaration in list {
+                        match parse_argument(declaration.as_str()) {
+                            Ok(Argument::Column(column)) => replace.push(column),
+                            _ => {
+                                return Err(Error::new_message(format!(
+                                    "Invalid column declaration '{declaration}' in '{arg}'"
+                                )))
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    return Err(Error::new_message(format!(
+                        "Unknown column selector modifier '{keyword}' in '{arg}', expected EXCLUDE or REPLACE"
+                    )))
+                }
+            }
+            rest = after;
+        }
+        return Ok(Some(ColumnSelector::All { exclude, replace }));
+    }
+    let regex_selector = arg
+        .get(..7)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("columns"))
+        .map(|_| &arg[7..])
+        .filter(|rest| rest.trim_start().starts_with('('));
+    if let Some(rest) = regex_selector {
+        let (list, after) = parenthesized(rest, arg)?;
+        let pattern = match list.as_slice() {
+            [pattern] => unquote(pattern),
+            _ => {
+                return Err(Error::new_message(format!(
+                    "columns() takes a single regex, like columns('^score_'), in '{arg}'"
+                )))
+            }
+        };
+        let pattern = Regex::new(&pattern).map_err(|e| {
+            Error::new_message(format!("Invalid regex '{pattern}' in '{arg}': {e}"))
+        })?;
+        let declared_type = Some(after.trim())
+            .filter(|t| !t.is_empty())
+            .map(String::from);
+        return Ok(Some(ColumnSelector::Regex {
+            pattern,
+            declared_type,
+        }));
+    }
+    Ok(None)
+}
+
+/// Splits a leading "(a, b, c)" into its comma-separated items, returning the
+/// items and the rest of `s` after the closing paren. Commas inside nested
+/// parens (ex. "decimal(10, 2)") or quotes don't split.
+fn parenthesized<'a>(s: &'a str, arg: &str) -> Result<(Vec<String>, &'a str)> {
+    let missing = || Error::new_message(format!("Expected a parenthesized list in '{arg}'"));
+    let s = s.trim_start().strip_prefix('(').ok_or_else(missing)?;
+    let mut items = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    for (idx, c) in s.char_indices() {
+        match (c, quote) {
+            (q, Some(open)) if q == open => quote = None,
+            (_, Some(_)) => (),
+            ('\'' | '"', None) => quote = Some(c),
+            ('(', None) => depth += 1,
+            (')', None) if depth == 0 => {
+                if !current.trim().is_empty() {
+                    items.push(current.trim().to_owned());
+                }
+                return Ok((items, &s[idx + 1..]));
+            }
+            (')', None) => depth -= 1,
+            (',', None) if depth == 0 => {
+                items.push(current.trim().to_owned());
+                current.clear();
+                continue;
+            }
+            _ => (),
+        }
+        current.push(c);
+    }
+    Err(missing())
+}
+
+/// Strips matching single or double quotes around a name or regex.
+fn unquote(s: &str) -> String {
+    for q in ['\'', '"'] {
+        if let Some(inner) = s.strip_prefix(q).and_then(|s| s.strip_suffix(q)) {
+            return inner.to_owned();
+        }
+    }
+    s.to_owned()
+}
+
+/// Expands column selectors against `headers`, the header row of `source`,
+/// into concrete column declarations. `headers` is only needed if there are
+/// selectors.
+pub fn resolve_column_specs(
+    specs: Vec<ColumnSpec>,
+    headers: Option<&StringRecord>,
+    source: &str,
+) -> Result<Vec<ColumnDeclaration>> {
+    let mut columns = vec![];
+    for spec in specs {
+        let selector = match spec {
+            ColumnSpec::Declared(column) => {
+                columns.push(column);
+                continue;
+            }
+            ColumnSpec::Selector(selector) => selector,
+        };
+        let headers = headers.ok_or_else(|| {
+            Error::new_message(
+                "column selectors like '*' require a sample file with headers, like sniff='data.csv'",
+            )
+        })?;
+        let has_header = |name: &str| headers.iter().any(|h| h.trim().eq_ignore_ascii_case(name));
+        match selector {
+            ColumnSelector::All { exclude, replace } => {
+                for name in exclude
+                    .iter()
+                    .chain(replace.iter().map(|column| &column.name))
+                {
+                    if !has_header(name) {
+                        return Err(Error::new_message(format!(
+                            "Column '{name}' not found in the headers of {source}"
+                        )));
+                    }
+                }
+                for header in headers.iter().map(str::trim) {
+                    if exclude.iter().any(|name| name.eq_ignore_ascii_case(header)) {
+                        continue;
+                    }
+                    match replace
+                        .iter()
+                        .find(|column| column.name.eq_ignore_ascii_case(header))
+                    {
+                        Some(column) => columns.push(ColumnDeclaration {
+                            name: column.name.clone(),
+                            declared_type: column.declared_type.clone(),
+                            constraints: column.constraints.clone(),
+                        }),
+                        None => columns.push(ColumnDeclaration {
+                            name: header.to_owned(),
+                            declared_type: None,
+                            constraints: None,
+                        }),
+                    }
+                }
+            }
+            ColumnSelector::Regex {
+                pattern,
+                declared_type,
+            } => {
+                let before = columns.len();
+                for header in headers.iter().map(str::trim) {
+                    if pattern.is_match(header) {
+                        columns.push(ColumnDeclaration {
+                            name: header.to_owned(),
+                            declared_type: declared_type.clone(),
+                            constraints: None,
+                        });
+                    }
+                }
+                if columns.len() == before {
+                    return Err(Error::new_message(format!(
+                        "columns('{pattern}') matched no headers of {source}"
+                    )));
+                }
+            }
+        }
+    }
+    Ok(columns)
+}
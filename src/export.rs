@@ -0,0 +1,261 @@
+//! Writing the results of a query to a CSV file, with csv_export(),
+//! tsv_export() and xsv_export().
+//!
+//! ```sql
+//! select csv_export('select * from students where age > 10', 'students.csv.gz');
+//! ```
+
+use sqlite_loadable::api::ValueType;
+use sqlite_loadable::ext::sqlite3ext_context_db_handle;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{api, Error, Result};
+use std::io::Write;
+
+use crate::statement::Statement;
+use crate::util::{get_csv_destination_writer, json_bool_option, json_character_option};
+
+/// Options for export functions, given as a JSON object in the last argument,
+/// like `csv_export(query, path, '{"header": false, "null_as": "\\N"}')`.
+pub struct ExportOptions {
+    pub header: bool,
+    quote: u8,
+    quote_style: csv::QuoteStyle,
+    terminator: csv::Terminator,
+    null_as: String,
+    /// start a new file after this many rows, for *_export_partitioned
+    pub max_rows: Option<u64>,
+    /// start a new file after this many (uncompressed) bytes, for *_export_partitioned
+    pub max_bytes: Option<u64>,
+    /// result column to split files by, for *_export_partitioned
+    pub partition_by: Option<String>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            header: true,
+            quote: b'"',
+            quote_style: csv::QuoteStyle::Necessary,
+            terminator: csv::Terminator::Any(b'\n'),
+            null_as: String::new(),
+            max_rows: None,
+            max_bytes: None,
+            partition_by: None,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Parses the options argument of an export function, or the defaults if
+    /// it's missing or NULL. `partitioned` allows the options that only
+    /// *_export_partitioned supports.
+    pub fn from_value(value: Option<&*mut sqlite3_value>, partitioned: bool) -> Result<Self> {
+        match value {
+            Some(value) if !matches!(api::value_type(value), ValueType::Null) => {
+                ExportOptions::parse(api::value_text(value)?, partitioned)
+            }
+            _ => Ok(ExportOptions::default()),
+        }
+    }
+
+    fn parse(options: &str, partitioned: bool) -> Result<ExportOptions> {
+        let options: serde_json::Value = serde_json::from_str(options)
+            .map_err(|e| Error::new_message(format!("export options must be valid JSON: {e}")))?;
+        let options = options.as_object().ok_or_else(|| {
+            Error::new_message("export options must be a JSON object, like '{\"header\": false}'")
+        })?;
+        let mut result = ExportOptions::default();
+        for (key, value) in options {
+            match key.as_str() {
+                "header" => result.header = json_bool_option(key, value)?,
+                "quote" => result.quote = json_character_option(key, value)?,
+                "quote_style" => {
+                    result.quote_style = match value.as_str() {
+                        Some("necessary") => csv::QuoteStyle::Necessary,
+                        Some("always") => csv::QuoteStyle::Always,
+                        Some("non_numeric") => csv::QuoteStyle::NonNumeric,
+                        Some("never") => csv::QuoteStyle::Never,
+                        _ => return Err(Error::new_message(
+                            "'quote_style' option must be one of 'necessary', 'always', 'non_numeric', or 'never'",
+                        )),
+                    }
+                }
+                "line_terminator" => {
+                    result.terminator = match value.as_str() {
+                        Some("lf") => csv::Terminator::Any(b'\n'),
+                        Some("crlf") => csv::Terminator::CRLF,
+                        _ => {
+                            return Err(Error::new_message(
+                                "'line_terminator' option must be 'lf' or 'crlf'",
+                            ))
+                        }
+                    }
+                }
+                "null_as" => {
+                    result.null_as = value
+                        .as_str()
+                        .ok_or_else(|| Error::new_message("'null_as' option must be a string"))?
+                        .to_owned()
+                }
+                "max_rows" | "max_bytes" | "partition_by" if !partitioned => {
+                    return Err(Error::new_message(format!(
+                        "'{key}' option is only supported by the *_export_partitioned functions"
+                    )))
+                }
+                "max_rows" => result.max_rows = Some(json_positive_integer(key, value)?),
+                "max_bytes" => result.max_bytes = Some(json_positive_integer(key, value)?),
+                "partition_by" => {
+                    result.partition_by = Some(
+                        value
+                            .as_str()
+                            .ok_or_else(|| {
+                                Error::new_message("'partition_by' option must be a column name")
+                            })?
+                            .to_owned(),
+                    )
+                }
+                _ => return Err(Error::new_message(format!("Unknown export option '{key}'"))),
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn writer_builder(&self, delimiter: u8) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+        builder
+            .delimiter(delimiter)
+            .quote(self.quote)
+            .quote_style(self.quote_style)
+            .terminator(self.terminator);
+        builder
+    }
+}
+
+fn json_positive_integer(key: &str, value: &serde_json::Value) -> Result<u64> {
+    value
+        .as_u64()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| Error::new_message(format!("'{key}' option must be a positive integer")))
+}
+
+/// The SQL to export for the first argument of an export function: either a
+/// statement, or the name of a table or view (optionally schema-qualified).
+pub fn export_sql(query_or_table: &str) -> String {
+    let query_or_table = query_or_table.trim();
+    if query_or_table.contains(char::is_whitespace) || query_or_table.is_empty() {
+        return query_or_table.to_owned();
+    }
+    let name = query_or_table
+        .split('.')
+        .map(|part| format!("\"{}\"", part.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(".");
+    format!("select * from {name}")
+}
+
+fn write_error(e: csv::Error) -> Error {
+    Error::new_message(format!("Error writing CSV: {e}"))
+}
+
+/// Writes the column names of `stmt` as a record.
+pub fn write_header<W: Write>(stmt: &Statement, writer: &mut csv::Writer<W>) -> Result<()> {
+    let headers = (0..stmt.column_count())
+        .map(|idx| stmt.column_name(idx))
+        .collect::<Result<Vec<String>>>()?;
+    writer.write_record(&headers).map_err(write_error)
+}
+
+/// Writes the current row of `stmt` as a record.
+pub fn write_current_row<W: Write>(
+    stmt: &Statement,
+    writer: &mut csv::Writer<W>,
+    options: &ExportOptions,
+) -> Result<()> {
+    for idx in 0..stmt.column_count() {
+        let value = stmt.column_value(idx);
+        match api::value_type(&value) {
+            ValueType::Null => writer.write_field(options.null_as.as_bytes()),
+            ValueType::Blob => writer.write_field(api::value_blob(&value)),
+            // integers and reals are converted to text by SQLite
+            _ => writer.write_field(api::value_text(&value)?.as_bytes()),
+        }
+        .map_err(write_error)?;
+    }
+    writer.write_record(None::<&[u8]>).map_err(write_error)
+}
+
+/// Writes every row of `stmt` to `writer` (after its header row, if enabled),
+/// returning the number of rows written.
+fn write_statement<W: Write>(
+    stmt: &mut Statement,
+    writer: &mut csv::Writer<W>,
+    options: &ExportOptions,
+) -> Result<i64> {
+    if options.header {
+        write_header(stmt, writer)?;
+    }
+    let mut rows = 0;
+    while stmt.step()? {
+        write_current_row(stmt, writer, options)?;
+        rows += 1;
+    }
+    writer
+        .flush()
+        .map_err(|e| Error::new_message(format!("Error writing CSV: {e}")))?;
+    Ok(rows)
+}
+
+/// Shared implementation of the export functions. Arguments are
+/// `(query_or_table, path, [options])`, with a delimiter argument after
+/// `path` when `delimiter` is None (xsv_export).
+fn export(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    delimiter: Option<u8>,
+) -> Result<()> {
+    let sql = export_sql(api::value_text(&values[0])?);
+    let path = api::value_text(&values[1])?;
+    let (delimiter, options_arg) = match delimiter {
+        Some(delimiter) => (delimiter, values.get(2)),
+        None => (
+            api::value_text(&values[2])?
+                .as_bytes()
+                .first()
+                .copied()
+                .ok_or_else(|| {
+                    Error::new_message("a delimiter is required, like xsv_export(query, path, '|')")
+                })?,
+            values.get(3),
+        ),
+    };
+    let options = ExportOptions::from_value(options_arg, false)?;
+
+    let db = unsafe { sqlite3ext_context_db_handle(context) };
+    let mut stmt = Statement::prepare(db, &sql)?;
+    let mut writer = options
+        .writer_builder(delimiter)
+        .from_writer(get_csv_destination_writer(path, false)?);
+    let rows = write_statement(&mut stmt, &mut writer, &options)?;
+    writer
+        .into_inner()
+        .map_err(|e| Error::new_message(format!("Error writing CSV: {e}")))?
+        .finish()
+        .map_err(|e| Error::new_message(format!("Error writing CSV: {e}")))?;
+    api::result_int64(context, rows);
+    Ok(())
+}
+
+/// xsv_export(query_or_table, path, delimiter, [options])
+pub fn xsv_export(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    export(context, values, None)
+}
+
+/// csv_export(query_or_table, path, [options])
+pub fn csv_export(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    export(context, values, Some(b','))
+}
+
+/// tsv_export(query_or_table, path, [options])
+pub fn tsv_export(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    export(context, values, Some(b'\t'))
+}
@@ -0,0 +1,459 @@
+/**
+ * A SQLite table function that exports the results of a query into multiple
+ * CSV files, and yields a manifest of every file written.
+ *
+ * Files are rotated after `max_rows` rows or `max_bytes` bytes, and split into
+ * separate files by the value of a `partition_by` column. The destination is a
+ * path template, where "{part}" is replaced with a 5-digit file number and
+ * "{key}" with the partition value.
+ *
+ * ```sql
+ * select * from csv_export_partitioned(
+ *   'select * from sales',
+ *   'out/region={key}/part-{part}.csv.gz',
+ *   '{"partition_by": "region", "max_rows": 100000}'
+ * );
+ * ```
+ */
+use sqlite_loadable::api::ValueType;
+use sqlite_loadable::ext::sqlite3ext_vtab_config;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{
+    api,
+    table::{IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Error, Result,
+};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::{mem, os::raw::c_int};
+
+use crate::export::{export_sql, write_current_row, write_header, ExportOptions};
+use crate::statement::Statement;
+use crate::util::{get_csv_destination_writer, DestinationWriter};
+
+/// sqlite3_vtab_config() option that keeps a virtual table out of triggers and views
+const SQLITE_VTAB_DIRECTONLY: c_int = 3;
+
+static CREATE_SQL: &str = "CREATE TABLE x(path, rows, bytes, query hidden, destination hidden, delimiter hidden, options hidden)";
+// csv_/tsv_export_partitioned have a fixed delimiter, so options is the 3rd argument
+static CREATE_SQL_FIXED_DELIMITER: &str =
+    "CREATE TABLE x(path, rows, bytes, query hidden, destination hidden, options hidden)";
+enum Columns {
+    Path,
+    Rows,
+    Bytes,
+    Query,
+    Destination,
+    Delimiter,
+    Options,
+}
+fn column(index: i32, fixed_delimiter: bool) -> Option<Columns> {
+    match (index, fixed_delimiter) {
+        (0, _) => Some(Columns::Path),
+        (1, _) => Some(Columns::Rows),
+        (2, _) => Some(Columns::Bytes),
+        (3, _) => Some(Columns::Query),
+        (4, _) => Some(Columns::Destination),
+        (5, false) => Some(Columns::Delimiter),
+        (5, true) | (6, false) => Some(Columns::Options),
+        _ => None,
+    }
+}
+
+/// A file written by an export, and the row of the manifest it's reported in.
+struct ManifestEntry {
+    path: String,
+    rows: i64,
+    bytes: i64,
+}
+
+/// Most files kept open at once. Past this, the least recently opened file is
+/// closed, and re-opened for appending when its partition has more rows.
+const MAX_OPEN_FILES: usize = 64;
+
+/// The file currently being written for a partition.
+struct PartFile {
+    path: String,
+    /// None while closed to stay under MAX_OPEN_FILES
+    writer: Option<DestinationWriter>,
+    /// index into the manifest
+    entry: usize,
+    rows: u64,
+    /// uncompressed bytes written so far, for max_bytes
+    bytes: u64,
+}
+
+struct Partition {
+    /// the partition column value this partition's key was made from
+    value: Option<String>,
+    /// number of files started for this partition, the "{part}" of the next file is part + 1
+    part: usize,
+    file: Option<PartFile>,
+    /// serializes this partition's records, before they're written to `file`
+    record: csv::Writer<Vec<u8>>,
+}
+
+/// Value of "{key}" for a partition column value. Path separators are replaced
+/// so every partition is a single path segment. Different values can map to
+/// the same key, which is an error.
+fn partition_key(value: Option<&str>) -> String {
+    match value {
+        None => String::from("__null__"),
+        Some("") => String::from("__empty__"),
+        Some(value) => value
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '\0' => '_',
+                c => c,
+            })
+            .collect::<String>()
+            .replace("..", "__"),
+    }
+}
+
+fn write_error(e: std::io::Error) -> Error {
+    Error::new_message(format!("Error writing CSV: {e}"))
+}
+
+/// Closes the writer of `file`, if it's open.
+fn close_file(file: &mut PartFile) -> Result<()> {
+    match file.writer.take() {
+        Some(writer) => writer.finish().map_err(write_error),
+        None => Ok(()),
+    }
+}
+
+/// Writes the record serialized into `partition.record` to its file,
+/// returning the number of bytes written.
+fn write_record(partition: &mut Partition) -> Result<u64> {
+    partition.record.flush().map_err(write_error)?;
+    let bytes = partition.record.get_mut();
+    let file = partition
+        .file
+        .as_mut()
+        .and_then(|file| file.writer.as_mut())
+        .ok_or_else(|| Error::new_message("Internal sqlite-xsv error: expected an open file"))?;
+    file.write_all(bytes).map_err(write_error)?;
+    let len = bytes.len() as u64;
+    bytes.clear();
+    Ok(len)
+}
+
+/// Runs the export, returning the manifest of files written.
+fn export_partitioned(
+    db: *mut sqlite3,
+    query_or_table: &str,
+    destination: &str,
+    delimiter: u8,
+    options: &ExportOptions,
+) -> Result<Vec<ManifestEntry>> {
+    let rotates = options.max_rows.is_some() || options.max_bytes.is_some();
+    if rotates && !destination.contains("{part}") {
+        return Err(Error::new_message(
+            "the destination must include {part} when max_rows or max_bytes is set, like 'out/part-{part}.csv'",
+        ));
+    }
+    if options.partition_by.is_some() != destination.contains("{key}") {
+        return Err(Error::new_message(
+            "the destination must include {key} if and only if partition_by is set, like 'out/region={key}/data.csv'",
+        ));
+    }
+
+    let mut stmt = Statement::prepare(db, &export_sql(query_or_table))?;
+    let key_column = match &options.partition_by {
+        Some(name) => Some(
+            (0..stmt.column_count())
+                .find(|idx| {
+                    stmt.column_name(*idx)
+                        .map_or(false, |column| column.eq_ignore_ascii_case(name))
+                })
+                .ok_or_else(|| {
+                    Error::new_message(format!(
+                        "partition_by column '{name}' is not a column of the query"
+                    ))
+                })?,
+        ),
+        None => None,
+    };
+    let builder = options.writer_builder(delimiter);
+
+    let mut manifest: Vec<ManifestEntry> = vec![];
+    let mut partitions: HashMap<String, Partition> = HashMap::new();
+    // keys of the partitions with an open file, oldest first
+    let mut open_files: VecDeque<String> = VecDeque::new();
+    while stmt.step()? {
+        let (key, value) = match key_column {
+            Some(idx) => {
+                let value = stmt.column_value(idx);
+                let value = if matches!(api::value_type(&value), ValueType::Null) {
+                    None
+                } else {
+                    Some(api::value_text(&value)?.to_owned())
+                };
+                (partition_key(value.as_deref()), value)
+            }
+            None => (String::new(), None),
+        };
+        let partition = partitions.entry(key.clone()).or_insert_with(|| Partition {
+            value: value.clone(),
+            part: 0,
+            file: None,
+            record: builder.from_writer(vec![]),
+        });
+        if partition.value != value {
+            let describe =
+                |value: Option<&str>| value.map_or("NULL".to_owned(), |v| format!("'{v}'"));
+            return Err(Error::new_message(format!(
+                "partition values {} and {} both have the key '{key}', so they would be written to the same files",
+                describe(partition.value.as_deref()),
+                describe(value.as_deref())
+            )));
+        }
+        let full = partition.file.as_ref().map_or(false, |file| {
+            options.max_rows.map_or(false, |max| file.rows >= max)
+                || options.max_bytes.map_or(false, |max| file.bytes >= max)
+        });
+        if full {
+            let mut file = partition.file.take().unwrap();
+            if file.writer.is_some() {
+                open_files.retain(|open| *open != key);
+            }
+            close_file(&mut file)?;
+        }
+        let new_file = partition.file.is_none();
+        if new_file {
+            partition.part += 1;
+            let path = destination
+                .replace("{key}", &key)
+                .replace("{part}", &format!("{:05}", partition.part));
+            if let Some(parent) = std::path::Path::new(&path).parent() {
+                std::fs::create_dir_all(parent).map_err(|e| {
+                    Error::new_message(format!("Error creating directory for {path}: {e}"))
+                })?;
+            }
+            partition.file = Some(PartFile {
+                path: path.clone(),
+                writer: None,
+                entry: manifest.len(),
+                rows: 0,
+                bytes: 0,
+            });
+            manifest.push(ManifestEntry {
+                path,
+                rows: 0,
+                bytes: 0,
+            });
+        }
+
+        if partition
+            .file
+            .as_ref()
+            .map_or(false, |file| file.writer.is_none())
+        {
+            if open_files.len() >= MAX_OPEN_FILES {
+                if let Some(oldest) = open_files.pop_front() {
+                    if let Some(file) = partitions
+                        .get_mut(&oldest)
+                        .and_then(|partition| partition.file.as_mut())
+                    {
+                        close_file(file)?;
+                    }
+                }
+            }
+            let partition = partitions.get_mut(&key).unwrap();
+            let file = partition.file.as_mut().unwrap();
+            // a file that was closed to stay under MAX_OPEN_FILES is appended to
+            file.writer = Some(get_csv_destination_writer(&file.path, !new_file)?);
+            open_files.push_back(key.clone());
+            if new_file && options.header {
+                write_header(&stmt, &mut partition.record)?;
+                let bytes = write_record(partition)?;
+                partition.file.as_mut().unwrap().bytes += bytes;
+            }
+        }
+
+        let partition = partitions.get_mut(&key).unwrap();
+        write_current_row(&stmt, &mut partition.record, options)?;
+        let bytes = write_record(partition)?;
+        let file = partition.file.as_mut().unwrap();
+        file.rows += 1;
+        file.bytes += bytes;
+        manifest[file.entry].rows += 1;
+    }
+    for (_, mut partition) in partitions.drain() {
+        if let Some(file) = partition.file.as_mut() {
+            close_file(file)?;
+        }
+    }
+    // compressed sizes are only known once files are finished, so they're
+    // read from disk afterwards
+    for entry in manifest.iter_mut() {
+        entry.bytes = std::fs::metadata(&entry.path)
+            .map_err(|e| Error::new_message(format!("Error reading {}: {e}", entry.path)))?
+            .len() as i64;
+    }
+    Ok(manifest)
+}
+
+#[repr(C)]
+pub struct XsvExportPartitionedTable {
+    /// must be first
+    base: sqlite3_vtab,
+    db: *mut sqlite3,
+    delimiter: Option<u8>,
+}
+
+impl<'vtab> VTab<'vtab> for XsvExportPartitionedTable {
+    type Aux = u8;
+    type Cursor = XsvExportPartitionedCursor;
+
+    fn connect(
+        db: *mut sqlite3,
+        aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, XsvExportPartitionedTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        // writes files, so like the scalar export functions it can't be used
+        // from triggers or views. Older SQLite versions without this option
+        // ignore it.
+        unsafe { sqlite3ext_vtab_config(db, SQLITE_VTAB_DIRECTONLY) };
+
+        let vtab = XsvExportPartitionedTable {
+            base,
+            db,
+            delimiter: aux.copied(),
+        };
+        let sql = if vtab.delimiter.is_some() {
+            CREATE_SQL_FIXED_DELIMITER
+        } else {
+            CREATE_SQL
+        };
+
+        Ok((sql.to_string(), vtab))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_query = false;
+        let mut has_destination = false;
+        let requires_delimiter = self.delimiter.is_none();
+        for mut constraint in info.constraints() {
+            let argv_index = match column(constraint.column_idx(), !requires_delimiter) {
+                Some(Columns::Query) => {
+                    has_query = true;
+                    1
+                }
+                Some(Columns::Destination) => {
+                    has_destination = true;
+                    2
+                }
+                Some(Columns::Delimiter) => 3,
+                Some(Columns::Options) => {
+                    if requires_delimiter {
+                        4
+                    } else {
+                        3
+                    }
+                }
+                _ => continue,
+            };
+            if !constraint.usable()
+                || constraint.op() != Some(sqlite_loadable::table::ConstraintOperator::EQ)
+            {
+                return Err(BestIndexError::Constraint);
+            }
+            constraint.set_omit(true);
+            constraint.set_argv_index(argv_index);
+        }
+        if !has_query || !has_destination {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100);
+        info.set_idxnum(1);
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<XsvExportPartitionedCursor> {
+        XsvExportPartitionedCursor::new(self.db, self.delimiter)
+    }
+}
+
+#[repr(C)]
+pub struct XsvExportPartitionedCursor {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    db: *mut sqlite3,
+    delimiter: Option<u8>,
+    manifest: Vec<ManifestEntry>,
+    rowid: i64,
+}
+impl XsvExportPartitionedCursor {
+    fn new(db: *mut sqlite3, delimiter: Option<u8>) -> Result<XsvExportPartitionedCursor> {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        Ok(XsvExportPartitionedCursor {
+            base,
+            db,
+            delimiter,
+            manifest: vec![],
+            rowid: 0,
+        })
+    }
+}
+
+impl VTabCursor for XsvExportPartitionedCursor {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let query = api::value_text(&values[0])?;
+        let destination = api::value_text(&values[1])?;
+        let (delimiter, options_arg) = match self.delimiter {
+            Some(delimiter) => (Some(delimiter), values.get(2)),
+            None => (
+                match values.get(2) {
+                    Some(value) => api::value_text(value)?.as_bytes().first().copied(),
+                    None => None,
+                },
+                values.get(3),
+            ),
+        };
+        let delimiter = delimiter.ok_or_else(|| {
+            Error::new_message(
+                "a delimiter is required, like xsv_export_partitioned(query, destination, '|')",
+            )
+        })?;
+        let options = ExportOptions::from_value(options_arg, true)?;
+        self.manifest = export_partitioned(self.db, query, destination, delimiter, &options)?;
+        self.rowid = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid as usize >= self.manifest.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let entry = &self.manifest[self.rowid as usize];
+        match column(i, self.delimiter.is_some()) {
+            Some(Columns::Path) => api::result_text(context, entry.path.as_str())?,
+            Some(Columns::Rows) => api::result_int64(context, entry.rows),
+            Some(Columns::Bytes) => api::result_int64(context, entry.bytes),
+            _ => api::result_null(context),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
@@ -0,0 +1,180 @@
+//! Listing the files a glob pattern matches, in a deterministic order, for the
+//! `xsv` virtual tables.
+
+use sqlite_loadable::vtab_argparse::ConfigOptionValue;
+use sqlite_loadable::{Error, Result};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// The order the files matched by a glob pattern are read in.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GlobOrder {
+    /// By path. The default.
+    Name,
+    /// Least recently modified first, ties broken by path.
+    Mtime,
+    /// Smallest first, ties broken by path.
+    Size,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct GlobOptions {
+    pub order: GlobOrder,
+    pub case_insensitive: bool,
+    /// whether wildcards match names that start with '.'
+    pub hidden: bool,
+    /// whether to read files that are (or are inside) symlinks
+    pub follow_symlinks: bool,
+    /// skip files and directories that can't be read, instead of failing
+    pub skip_unreadable: bool,
+}
+
+impl Default for GlobOptions {
+    fn default() -> Self {
+        GlobOptions {
+            order: GlobOrder::Name,
+            case_insensitive: false,
+            hidden: true,
+            follow_symlinks: true,
+            skip_unreadable: false,
+        }
+    }
+}
+
+pub fn parse_glob_order_config_value(value: ConfigOptionValue) -> Result<GlobOrder> {
+    match value {
+        ConfigOptionValue::Quoted(value) | ConfigOptionValue::Bareword(value) => {
+            match value.to_lowercase().as_str() {
+                "name" => Ok(GlobOrder::Name),
+                "mtime" => Ok(GlobOrder::Mtime),
+                "size" => Ok(GlobOrder::Size),
+                _ => Err(Error::new_message(format!(
+                    "Unknown glob_order value '{value}', expected 'name', 'mtime', or 'size'"
+                ))),
+            }
+        }
+        _ => Err(Error::new_message("Unknown glob_order value")),
+    }
+}
+
+/// The directory before the first component of `pattern` with glob characters.
+fn literal_prefix(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|component| {
+            !component
+                .as_os_str()
+                .to_string_lossy()
+                .contains(|c| matches!(c, '*' | '?' | '['))
+        })
+        .collect()
+}
+
+/// Whether `path`, or any directory between it and `prefix`, is a symlink.
+fn crosses_symlink(path: &Path, prefix: &Path) -> bool {
+    path.ancestors()
+        .take_while(|ancestor| *ancestor != prefix && !ancestor.as_os_str().is_empty())
+        .any(|ancestor| {
+            std::fs::symlink_metadata(ancestor).map_or(false, |m| m.file_type().is_symlink())
+        })
+}
+
+/// Every file matching the glob `pattern`, sorted by `options.order`.
+pub fn matching_files(pattern: &str, options: &GlobOptions) -> Result<Vec<PathBuf>> {
+    let match_options = glob::MatchOptions {
+        case_sensitive: !options.case_insensitive,
+        require_literal_separator: false,
+        require_literal_leading_dot: !options.hidden,
+    };
+    let paths = glob::glob_with(pattern, match_options)
+        .map_err(|e| Error::new_message(format!("Invalid glob pattern for {pattern}: {e}")))?;
+    let prefix = literal_prefix(pattern);
+    let mut files = vec![];
+    for path in paths {
+        let path = match path {
+            Ok(path) => path,
+            Err(_) if options.skip_unreadable => continue,
+            Err(error) => {
+                return Err(Error::new_message(format!(
+                    "Error on next glob match: {error}. Use skip_unreadable=on to skip files and directories that can't be read."
+                )))
+            }
+        };
+        if !options.follow_symlinks && crosses_symlink(&path, &prefix) {
+            continue;
+        }
+        if options.skip_unreadable && File::open(&path).is_err() {
+            continue;
+        }
+        files.push(path);
+    }
+
+    // files whose size or modification time can't be read, like dangling
+    // symlinks, sort last
+    let sort_key = |path: &PathBuf| -> Option<u128> {
+        let metadata = std::fs::metadata(path).ok()?;
+        match options.order {
+            GlobOrder::Name => Some(0),
+            GlobOrder::Size => Some(metadata.len().into()),
+            GlobOrder::Mtime => metadata
+                .modified()
+                .ok()?
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|mtime| mtime.as_nanos()),
+        }
+    };
+    if options.order == GlobOrder::Name {
+        files.sort();
+        return Ok(files);
+    }
+    let mut keyed: Vec<(bool, u128, PathBuf)> = files
+        .into_iter()
+        .map(|path| {
+            let key = sort_key(&path);
+            (key.is_none(), key.unwrap_or(0), path)
+        })
+        .collect();
+    keyed.sort();
+    Ok(keyed.into_iter().map(|(_, _, path)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::files::*;
+
+    #[test]
+    fn literal_prefix_stops_at_first_glob_component() {
+        assert_eq!(literal_prefix("data/*.csv"), PathBuf::from("data"));
+        assert_eq!(literal_prefix("/tmp/x/*.csv"), PathBuf::from("/tmp/x"));
+        assert_eq!(literal_prefix("/tmp/x/d?/a.csv"), PathBuf::from("/tmp/x"));
+        assert_eq!(literal_prefix("data/[ab].csv"), PathBuf::from("data"));
+        assert_eq!(literal_prefix("*.csv"), PathBuf::new());
+        assert_eq!(literal_prefix("data/a.csv"), PathBuf::from("data/a.csv"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn crosses_symlink_below_prefix() {
+        let base = std::env::temp_dir().join(format!("sqlite-xsv-files-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("real")).unwrap();
+        std::fs::write(base.join("real/a.csv"), "a\n1\n").unwrap();
+        std::os::unix::fs::symlink(base.join("real"), base.join("link")).unwrap();
+        std::os::unix::fs::symlink(base.join("real/a.csv"), base.join("real/b.csv")).unwrap();
+
+        assert!(!crosses_symlink(&base.join("real/a.csv"), &base));
+        assert!(crosses_symlink(&base.join("real/b.csv"), &base));
+        assert!(crosses_symlink(&base.join("link/a.csv"), &base));
+        // the prefix was written out in the pattern, so it is followed
+        assert!(!crosses_symlink(
+            &base.join("link/a.csv"),
+            &base.join("link")
+        ));
+        // relative paths stop at the empty path instead of walking up to cwd
+        assert!(!crosses_symlink(Path::new("a.csv"), Path::new("")));
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,317 @@
+//! Loading a CSV file into a new table with csv_import(), tsv_import() and
+//! xsv_import(), instead of copying out of a temporary `csv` virtual table.
+//!
+//! ```sql
+//! select csv_import('data.csv.gz', 'main.t', 'infer_types=1000,batch=50000');
+//! ```
+
+use sqlite_loadable::api::ValueType;
+use sqlite_loadable::ext::sqlite3ext_context_db_handle;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{api, Error, Result};
+
+use crate::statement::Statement;
+use crate::typed::parse_real;
+use crate::util::{get_csv_source_reader, json_bool_option, json_character_option};
+
+/// Options for import functions, given as either comma-separated `key=value`
+/// pairs like 'infer_types=1000,batch=50000', or a JSON object.
+struct ImportOptions {
+    header: bool,
+    quote: u8,
+    /// number of records to sample when inferring column types, 0 for all TEXT columns
+    infer_types: usize,
+    /// number of rows inserted per transaction
+    batch: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            header: true,
+            quote: b'"',
+            infer_types: 1000,
+            batch: 50000,
+        }
+    }
+}
+
+impl ImportOptions {
+    fn parse(options: &str) -> Result<ImportOptions> {
+        let options = if options.trim_start().starts_with('{') {
+            let options: serde_json::Value = serde_json::from_str(options).map_err(|e| {
+                Error::new_message(format!("import options must be valid JSON: {e}"))
+            })?;
+            options
+                .as_object()
+                .ok_or_else(|| Error::new_message("import options must be a JSON object"))?
+                .clone()
+        } else {
+            options
+                .split(',')
+                .filter(|pair| !pair.trim().is_empty())
+                .map(|pair| match pair.split_once('=') {
+                    Some((key, value)) => Ok((
+                        key.trim().to_owned(),
+                        serde_json::Value::String(value.trim().to_owned()),
+                    )),
+                    None => Err(Error::new_message(format!(
+                        "import option '{pair}' must be in the form key=value"
+                    ))),
+                })
+                .collect::<Result<serde_json::Map<String, serde_json::Value>>>()?
+        };
+        let mut result = ImportOptions::default();
+        for (key, value) in &options {
+            match key.as_str() {
+                "header" => result.header = json_bool_option(key, value)?,
+                "quote" => result.quote = json_character_option(key, value)?,
+                "infer_types" => result.infer_types = json_count_option(key, value, true)?,
+                "batch" => result.batch = json_count_option(key, value, false)?,
+                _ => return Err(Error::new_message(format!("Unknown import option '{key}'"))),
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// A non-negative integer option, given either as a JSON number or a string.
+fn json_count_option(key: &str, value: &serde_json::Value, allow_zero: bool) -> Result<usize> {
+    let count = match value {
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => s.parse::<u64>().ok(),
+        _ => None,
+    };
+    match count {
+        Some(count) if allow_zero || count > 0 => Ok(count as usize),
+        _ => Err(Error::new_message(format!(
+            "'{key}' option must be a {} integer",
+            if allow_zero {
+                "non-negative"
+            } else {
+                "positive"
+            }
+        ))),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum ColumnType {
+    Integer,
+    Real,
+    Text,
+}
+
+impl ColumnType {
+    fn declaration(&self) -> &'static str {
+        match self {
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+            ColumnType::Text => "TEXT",
+        }
+    }
+}
+
+/// Whether `field` would lose its formatting as a number, like the leading
+/// zeros of zip codes ("00501") or an explicit "+" sign.
+fn is_formatted_number(field: &str) -> bool {
+    let digits = field.strip_prefix('-').unwrap_or(field);
+    field.starts_with('+')
+        || (digits.starts_with('0') && digits[1..].starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Infers the type of every column from the non-empty fields of `records`:
+/// INTEGER if they're all integers, REAL if they're all numbers, otherwise TEXT.
+/// Numbers with leading zeros or a "+" sign keep the column TEXT.
+fn infer_types(records: &[csv::StringRecord], column_count: usize) -> Vec<ColumnType> {
+    (0..column_count)
+        .map(|idx| {
+            let mut fields = records
+                .iter()
+                .filter_map(|record| record.get(idx))
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .peekable();
+            if fields.peek().is_none() {
+                return ColumnType::Text;
+            }
+            fields.fold(ColumnType::Integer, |inferred, field| match inferred {
+                _ if is_formatted_number(field) => ColumnType::Text,
+                ColumnType::Integer if field.parse::<i64>().is_ok() => ColumnType::Integer,
+                ColumnType::Integer | ColumnType::Real if parse_real(field).is_some() => {
+                    ColumnType::Real
+                }
+                _ => ColumnType::Text,
+            })
+        })
+        .collect()
+}
+
+/// Quotes a possibly schema-qualified table name, like main.t -> "main"."t".
+fn quote_table_name(name: &str) -> String {
+    name.split('.')
+        .map(|part| format!("\"{}\"", part.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Inserts `record` with `insert`, as NULLs for empty INTEGER/REAL fields and
+/// for missing fields.
+fn insert_record(
+    insert: &mut Statement,
+    record: &csv::StringRecord,
+    types: &[ColumnType],
+) -> Result<()> {
+    for (idx, column_type) in types.iter().enumerate() {
+        let param = idx as i32 + 1;
+        match record.get(idx) {
+            Some(field) if *column_type == ColumnType::Text || !field.trim().is_empty() => {
+                insert.bind_text(param, field)?
+            }
+            _ => insert.bind_null(param)?,
+        }
+    }
+    insert.step()?;
+    insert.reset();
+    Ok(())
+}
+
+/// Shared implementation of the import functions. Arguments are
+/// `(path, table, [options])`, with a delimiter argument after `table` when
+/// `delimiter` is None (xsv_import).
+fn import(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    delimiter: Option<u8>,
+) -> Result<()> {
+    let path = api::value_text(&values[0])?;
+    let table = api::value_text(&values[1])?;
+    let (delimiter, options_arg) = match delimiter {
+        Some(delimiter) => (delimiter, values.get(2)),
+        None => (
+            api::value_text(&values[2])?
+                .as_bytes()
+                .first()
+                .copied()
+                .ok_or_else(|| {
+                    Error::new_message("a delimiter is required, like xsv_import(path, table, '|')")
+                })?,
+            values.get(3),
+        ),
+    };
+    let options = match options_arg {
+        Some(value) if !matches!(api::value_type(value), ValueType::Null) => {
+            ImportOptions::parse(api::value_text(value)?)?
+        }
+        _ => ImportOptions::default(),
+    };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(options.header)
+        .delimiter(delimiter)
+        .quote(options.quote)
+        .flexible(true)
+        .from_reader(get_csv_source_reader(path)?);
+    let read_error = |e: csv::Error| Error::new_message(format!("Error reading {path}: {e}"));
+    let headers = reader.headers().map_err(read_error)?.clone();
+    let columns: Vec<String> = if options.header {
+        headers.iter().map(|h| h.trim().to_owned()).collect()
+    } else {
+        (1..=headers.len()).map(|i| format!("c{i}")).collect()
+    };
+    if columns.is_empty() {
+        return Err(Error::new_message(format!("no columns in {path}")));
+    }
+
+    // sampled records are inserted before the rest of the file
+    let mut sample = vec![];
+    for _ in 0..options.infer_types.max(1) {
+        let mut record = csv::StringRecord::new();
+        if !reader.read_record(&mut record).map_err(read_error)? {
+            break;
+        }
+        sample.push(record);
+    }
+    let types = if options.infer_types == 0 {
+        vec![ColumnType::Text; columns.len()]
+    } else {
+        infer_types(&sample, columns.len())
+    };
+
+    let db = unsafe { sqlite3ext_context_db_handle(context) };
+    let table = quote_table_name(table);
+    let definitions: Vec<String> = columns
+        .iter()
+        .zip(&types)
+        .map(|(name, column_type)| {
+            format!(
+                "\"{}\" {}",
+                name.replace('"', "\"\""),
+                column_type.declaration()
+            )
+        })
+        .collect();
+    // each batch is a savepoint, which is its own transaction outside of one.
+    // The first batch also creates the table. On errors the current batch is
+    // rolled back, and the table is dropped if earlier batches were released,
+    // so a bad file leaves no table behind.
+    Statement::execute(db, "savepoint csv_import")?;
+    let mut rows: i64 = 0;
+    let mut released = false;
+    let result = (|| -> Result<()> {
+        Statement::execute(
+            db,
+            &format!("create table {table}({})", definitions.join(", ")),
+        )?;
+        let mut insert = Statement::prepare(
+            db,
+            &format!(
+                "insert into {table} values ({})",
+                vec!["?"; columns.len()].join(", ")
+            ),
+        )?;
+        for record in &sample {
+            insert_record(&mut insert, record, &types)?;
+            rows += 1;
+        }
+        let mut record = csv::StringRecord::new();
+        while reader.read_record(&mut record).map_err(read_error)? {
+            insert_record(&mut insert, &record, &types)?;
+            rows += 1;
+            if rows as usize % options.batch == 0 {
+                Statement::execute(db, "release csv_import")?;
+                released = true;
+                Statement::execute(db, "savepoint csv_import")?;
+            }
+        }
+        Ok(())
+    })();
+    match result {
+        Ok(()) => Statement::execute(db, "release csv_import")?,
+        Err(err) => {
+            Statement::execute(db, "rollback to csv_import")?;
+            Statement::execute(db, "release csv_import")?;
+            if released {
+                Statement::execute(db, &format!("drop table {table}"))?;
+            }
+            return Err(err);
+        }
+    }
+    api::result_int64(context, rows);
+    Ok(())
+}
+
+/// xsv_import(path, table, delimiter, [options])
+pub fn xsv_import(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    import(context, values, None)
+}
+
+/// csv_import(path, table, [options])
+pub fn csv_import(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    import(context, values, Some(b','))
+}
+
+/// tsv_import(path, table, [options])
+pub fn tsv_import(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    import(context, values, Some(b'\t'))
+}
@@ -1,16 +1,31 @@
+mod columns;
+mod export;
+mod export_partitioned;
+mod files;
+mod import;
 mod meta;
+#[cfg(feature = "s3_support")]
+mod s3;
+mod statement;
+mod typed;
 mod util;
 mod xsv;
 mod xsv_fields;
+mod xsv_files;
 mod xsv_reader;
 mod xsv_rows;
 
 use crate::{
+    export::{csv_export, tsv_export, xsv_export, ExportOptions},
+    export_partitioned::XsvExportPartitionedTable,
+    import::{csv_import, tsv_import, xsv_import},
     meta::{xsv_debug, xsv_version},
+    typed::{xsv_date, xsv_int, xsv_real},
     xsv::XsvTable,
     xsv_fields::XsvFieldsTable,
+    xsv_files::XsvFilesTable,
     xsv_reader::XsvReaderTable,
-    xsv_rows::XsvRowsTable,
+    xsv_rows::{RowsInput, XsvRowsAux, XsvRowsTable},
 };
 use csv::StringRecord;
 use sqlite_loadable::{
@@ -19,49 +34,141 @@ use sqlite_loadable::{
     define_virtual_table_with_find,
     prelude::*,
     table::define_table_function_with_find,
-    FunctionFlags, Result,
+    Error, FunctionFlags, Result,
 };
 
+#[derive(Clone)]
 struct RowPointer {
     row: StringRecord,
     headers: Option<StringRecord>,
+    /// delimiter of the source, used when re-serializing with xsv_slice()
+    delimiter: u8,
 }
 const ROW_POINTER_NAME: &[u8] = b"sqlite-xsv-row0\0";
 const HEADERS_POINTER_NAME: &[u8] = b"sqlite-xsv-headers0\0";
+/// pointer type of `Box<dyn SqliteReader>` objects from sqlite-reader extensions
+const READER_POINTER_NAME: &[u8] = b"reader0\0";
 
+impl RowPointer {
+    /// The row as a JSON object keyed by header, in header order. Fields without
+    /// a header are keyed by their 1-based position ("c4"), and when a header
+    /// appears more than once the last field wins.
+    fn to_json_object(&self) -> serde_json::Value {
+        let mut object = serde_json::Map::new();
+        for (idx, field) in self.row.iter().enumerate() {
+            let key = match self.headers.as_ref().and_then(|headers| headers.get(idx)) {
+                Some(header) => header.to_owned(),
+                None => format!("c{}", idx + 1),
+            };
+            object.insert(key, serde_json::Value::String(field.to_owned()));
+        }
+        serde_json::Value::Object(object)
+    }
+
+    /// The field of the row that `selector` refers to: an INTEGER index (negative
+    /// counts from the end) or a TEXT header name. `function` is only used in
+    /// error messages.
+    fn field(
+        &self,
+        function: &str,
+        selector: &*mut sqlite3_value,
+        case_insensitive: bool,
+    ) -> Result<Option<&str>> {
+        let idx = match api::value_type(selector) {
+            ValueType::Integer => resolve_index(api::value_int64(selector), self.row.len()),
+            ValueType::Text => {
+                let header = api::value_text(selector)?;
+                self.headers.as_ref().and_then(|headers| {
+                    headers.iter().position(|h| {
+                        if case_insensitive {
+                            h.to_lowercase() == header.to_lowercase()
+                        } else {
+                            h == header
+                        }
+                    })
+                })
+            }
+            _ => {
+                return Err(Error::new_message(format!(
+                    "{function}() selector must be an INTEGER index or TEXT header name, not {}",
+                    value_type_name(selector)
+                )))
+            }
+        };
+        Ok(idx.and_then(|idx| self.row.get(idx)))
+    }
+}
+
+fn record_to_json_array(record: &StringRecord) -> serde_json::Value {
+    serde_json::Value::Array(
+        record
+            .iter()
+            .map(|field| serde_json::Value::String(field.to_owned()))
+            .collect(),
+    )
+}
+
+/// The index into a record of `len` fields that `at` refers to, where negative
+/// values count back from the end (-1 is the last field).
+fn resolve_index(at: i64, len: usize) -> Option<usize> {
+    let idx = if at < 0 { len as i64 + at } else { at };
+    if idx < 0 || idx >= len as i64 {
+        None
+    } else {
+        Some(idx as usize)
+    }
+}
+
+fn value_type_name(value: &*mut sqlite3_value) -> &'static str {
+    match api::value_type(value) {
+        ValueType::Integer => "INTEGER",
+        ValueType::Float => "REAL",
+        ValueType::Text => "TEXT",
+        ValueType::Blob => "BLOB",
+        ValueType::Null => "NULL",
+    }
+}
+
+/// xsv_at(row, selector, [case_insensitive]), also used for the "->>" operator
+/// on xsv_rows. `selector` is either an INTEGER field index (negative counts from
+/// the end) or a TEXT header name.
 pub fn xsv_at(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
     // values[0] may legally be:
     //  1. RowPointer (the "row" column from xsv_rows/csv_rows/tsv_rows)
     //  2. StringRecord (the "headers" column from xsv_rows/csv_rows/tsv_rows)
-    //  3. TODO what else?
-    //
-    // values[1]
+    let selector = &values[1];
+    let case_insensitive = match values.get(2) {
+        None => false,
+        Some(value) => match api::value_type(value) {
+            ValueType::Integer => api::value_int64(value) != 0,
+            ValueType::Null => false,
+            _ => {
+                return Err(Error::new_message(format!(
+                    "xsv_at() case_insensitive must be an INTEGER, like 1 or true, not {}",
+                    value_type_name(value)
+                )))
+            }
+        },
+    };
     if let Some(record) = unsafe { api::value_pointer::<RowPointer>(&values[0], ROW_POINTER_NAME) }
     {
-        let at = match api::value_type(&values[1]) {
-            ValueType::Integer => api::value_int64(&values[1]),
-            ValueType::Text => {
-                let header = api::value_text(&values[1]).unwrap();
-                let headers = unsafe { (*record).headers.as_ref().unwrap() };
-                match headers.iter().position(|h| h == header) {
-                    Some(idx) => idx as i64,
-                    None => {
-                        api::result_null(context);
-                        return Ok(());
-                    }
-                }
-            }
-            _ => todo!(),
-        };
-        match unsafe { (*record).row.get(at as usize) } {
+        match unsafe { (*record).field("xsv_at", selector, case_insensitive)? } {
             Some(field) => api::result_text(context, field)?,
             None => api::result_null(context),
         }
     } else if let Some(headers) =
         unsafe { api::value_pointer::<StringRecord>(&values[0], HEADERS_POINTER_NAME) }
     {
-        let at = api::value_int64(&values[1]);
-        match unsafe { (*headers).get(at as usize) } {
+        let headers = unsafe { &*headers };
+        if !matches!(api::value_type(selector), ValueType::Integer) {
+            return Err(Error::new_message(format!(
+                "xsv_at() on headers requires an INTEGER index, not {}",
+                value_type_name(selector)
+            )));
+        }
+        match resolve_index(api::value_int64(selector), headers.len())
+            .and_then(|idx| headers.get(idx))
+        {
             Some(field) => api::result_text(context, field)?,
             None => api::result_null(context),
         }
@@ -69,6 +176,76 @@ pub fn xsv_at(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> R
     Ok(())
 }
 
+/// xsv_slice(row, start, [end], [options]): the fields of `row` from `start` up to
+/// but not including `end`, re-serialized as a single CSV record with the row's
+/// delimiter. Negative bounds count from the end, and out of range bounds are
+/// clamped. `options` are the same quoting options as csv_export().
+pub fn xsv_slice(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let record = unsafe { api::value_pointer::<RowPointer>(&values[0], ROW_POINTER_NAME) }
+        .ok_or_else(|| Error::new_message("xsv_slice() expects a row value from xsv_rows"))?;
+    let record = unsafe { &*record };
+    let len = record.row.len() as i64;
+    let bound = |value: Option<&*mut sqlite3_value>, default: i64| -> Result<usize> {
+        let at = match value {
+            None => default,
+            Some(value) => match api::value_type(value) {
+                ValueType::Integer => api::value_int64(value),
+                ValueType::Null => default,
+                _ => {
+                    return Err(Error::new_message(format!(
+                        "xsv_slice() bounds must be INTEGER, not {}",
+                        value_type_name(value)
+                    )))
+                }
+            },
+        };
+        let at = if at < 0 { len + at } else { at };
+        Ok(at.clamp(0, len) as usize)
+    };
+    let start = bound(values.get(1), 0)?;
+    let end = bound(values.get(2), len)?;
+    if start >= end {
+        api::result_text(context, "")?;
+        return Ok(());
+    }
+    let options = ExportOptions::from_value(values.get(3), false)?;
+    let mut writer = options
+        .writer_builder(record.delimiter)
+        // a single record, so always end with a single byte to drop below
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    writer
+        .write_record(record.row.iter().skip(start).take(end - start))
+        .map_err(|err| Error::new_message(format!("Error writing slice: {err}")))?;
+    let mut bytes = writer
+        .into_inner()
+        .map_err(|err| Error::new_message(format!("Error writing slice: {err}")))?;
+    // drop the record terminator
+    bytes.pop();
+    let slice = String::from_utf8(bytes)
+        .map_err(|_| Error::new_message("Internal sqlite-xsv error: invalid UTF8 in slice"))?;
+    api::result_text(context, slice.as_str())?;
+    Ok(())
+}
+
+/// xsv_to_json(row): a "row" from xsv_rows as a JSON object keyed by header,
+/// or a "headers" value as a JSON array.
+pub fn xsv_to_json(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    if let Some(record) = unsafe { api::value_pointer::<RowPointer>(&values[0], ROW_POINTER_NAME) }
+    {
+        api::result_json(context, unsafe { (*record).to_json_object() })?;
+    } else if let Some(headers) =
+        unsafe { api::value_pointer::<StringRecord>(&values[0], HEADERS_POINTER_NAME) }
+    {
+        api::result_json(context, record_to_json_array(unsafe { &*headers }))?;
+    } else {
+        return Err(Error::new_message(
+            "xsv_to_json() expects a row or headers value from xsv_rows",
+        ));
+    }
+    Ok(())
+}
+
 #[sqlite_entrypoint]
 pub fn sqlite3_xsv_init(db: *mut sqlite3) -> Result<()> {
     let comma = b',';
@@ -86,6 +263,50 @@ pub fn sqlite3_xsv_init(db: *mut sqlite3) -> Result<()> {
     define_scalar_function(db, "xsv_at", 2, xsv_at, FunctionFlags::DETERMINISTIC)?;
     define_scalar_function(db, "csv_at", 2, xsv_at, FunctionFlags::DETERMINISTIC)?;
     define_scalar_function(db, "tsv_at", 2, xsv_at, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "xsv_at", 3, xsv_at, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "csv_at", 3, xsv_at, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "tsv_at", 3, xsv_at, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "xsv_slice", 2, xsv_slice, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "csv_slice", 2, xsv_slice, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "tsv_slice", 2, xsv_slice, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "xsv_slice", 3, xsv_slice, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "csv_slice", 3, xsv_slice, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "tsv_slice", 3, xsv_slice, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "xsv_slice", 4, xsv_slice, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "csv_slice", 4, xsv_slice, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "tsv_slice", 4, xsv_slice, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "xsv_int", 2, xsv_int, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "xsv_int", 3, xsv_int, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "xsv_real", 2, xsv_real, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "xsv_real", 3, xsv_real, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "xsv_date", 2, xsv_date, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(db, "xsv_date", 3, xsv_date, FunctionFlags::DETERMINISTIC)?;
+    define_scalar_function(
+        db,
+        "xsv_to_json",
+        1,
+        xsv_to_json,
+        FunctionFlags::DETERMINISTIC,
+    )?;
+
+    // export functions write files, so they can't be used from triggers or views
+    let export_flags = FunctionFlags::UTF8 | FunctionFlags::DIRECTONLY;
+    define_scalar_function(db, "xsv_export", 3, xsv_export, export_flags)?;
+    define_scalar_function(db, "xsv_export", 4, xsv_export, export_flags)?;
+    define_scalar_function(db, "csv_export", 2, csv_export, export_flags)?;
+    define_scalar_function(db, "csv_export", 3, csv_export, export_flags)?;
+    define_scalar_function(db, "tsv_export", 2, tsv_export, export_flags)?;
+    define_scalar_function(db, "tsv_export", 3, tsv_export, export_flags)?;
+    define_table_function::<XsvExportPartitionedTable>(db, "xsv_export_partitioned", None)?;
+    define_table_function::<XsvExportPartitionedTable>(db, "csv_export_partitioned", Some(comma))?;
+    define_table_function::<XsvExportPartitionedTable>(db, "tsv_export_partitioned", Some(tab))?;
+    // import functions create tables, so they have the same restriction
+    define_scalar_function(db, "xsv_import", 3, xsv_import, export_flags)?;
+    define_scalar_function(db, "xsv_import", 4, xsv_import, export_flags)?;
+    define_scalar_function(db, "csv_import", 2, csv_import, export_flags)?;
+    define_scalar_function(db, "csv_import", 3, csv_import, export_flags)?;
+    define_scalar_function(db, "tsv_import", 2, tsv_import, export_flags)?;
+    define_scalar_function(db, "tsv_import", 3, tsv_import, export_flags)?;
 
     define_virtual_table::<XsvTable>(db, "xsv", None)?;
     define_virtual_table_with_find::<XsvTable>(db, "csv", Some(comma))?;
@@ -103,13 +324,61 @@ pub fn sqlite3_xsv_init(db: *mut sqlite3) -> Result<()> {
     define_virtual_table::<XsvReaderTable>(db, "csv_reader", Some(comma))?;
     define_virtual_table::<XsvReaderTable>(db, "tsv_reader", Some(tab))?;
 
-    define_table_function_with_find::<XsvRowsTable>(db, "xsv_rows", None)?;
-    define_table_function_with_find::<XsvRowsTable>(db, "csv_rows", Some(comma))?;
-    define_table_function_with_find::<XsvRowsTable>(db, "tsv_rows", Some(tab))?;
+    define_table_function_with_find::<XsvRowsTable>(
+        db,
+        "xsv_rows",
+        Some(XsvRowsAux {
+            delimiter: None,
+            input: RowsInput::Value,
+        }),
+    )?;
+    define_table_function_with_find::<XsvRowsTable>(
+        db,
+        "csv_rows",
+        Some(XsvRowsAux {
+            delimiter: Some(comma),
+            input: RowsInput::Value,
+        }),
+    )?;
+    define_table_function_with_find::<XsvRowsTable>(
+        db,
+        "tsv_rows",
+        Some(XsvRowsAux {
+            delimiter: Some(tab),
+            input: RowsInput::Value,
+        }),
+    )?;
+
+    define_table_function_with_find::<XsvRowsTable>(
+        db,
+        "xsv_rows_file",
+        Some(XsvRowsAux {
+            delimiter: None,
+            input: RowsInput::File,
+        }),
+    )?;
+    define_table_function_with_find::<XsvRowsTable>(
+        db,
+        "csv_rows_file",
+        Some(XsvRowsAux {
+            delimiter: Some(comma),
+            input: RowsInput::File,
+        }),
+    )?;
+    define_table_function_with_find::<XsvRowsTable>(
+        db,
+        "tsv_rows_file",
+        Some(XsvRowsAux {
+            delimiter: Some(tab),
+            input: RowsInput::File,
+        }),
+    )?;
+
+    define_table_function_with_find::<XsvFieldsTable>(db, "xsv_fields", None)?;
+    define_table_function_with_find::<XsvFieldsTable>(db, "csv_fields", None)?;
+    define_table_function_with_find::<XsvFieldsTable>(db, "tsv_fields", None)?;
 
-    define_table_function::<XsvFieldsTable>(db, "xsv_fields", None)?;
-    define_table_function::<XsvFieldsTable>(db, "csv_fields", None)?;
-    define_table_function::<XsvFieldsTable>(db, "tsv_fields", None)?;
+    define_table_function::<XsvFilesTable>(db, "xsv_files", None)?;
 
     Ok(())
 }
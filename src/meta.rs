@@ -11,10 +11,30 @@ pub fn xsv_debug(context: *mut sqlite3_context, _values: &[*mut sqlite3_value])
         format!(
             "Version: v{}
 Source: {}
+Features: {}
 ",
             env!("CARGO_PKG_VERSION"),
-            env!("GIT_HASH")
+            env!("GIT_HASH"),
+            features().join(", ")
         ),
     )?;
     Ok(())
 }
+
+/// Optional features this build was compiled with.
+fn features() -> Vec<&'static str> {
+    let mut features = vec![];
+    if cfg!(feature = "gzip_support") {
+        features.push("gzip_support");
+    }
+    if cfg!(feature = "zstd_support") {
+        features.push("zstd_support");
+    }
+    if cfg!(feature = "http_support") {
+        features.push("http_support");
+    }
+    if cfg!(feature = "s3_support") {
+        features.push("s3_support");
+    }
+    features
+}
@@ -0,0 +1,347 @@
+//! Reading CSVs from S3 and S3-compatible object storage (like MinIO) with
+//! `s3://bucket/key` paths, when built with the s3_support feature.
+//!
+//! Credentials and the endpoint come from the standard environment variables:
+//! AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, AWS_SESSION_TOKEN, AWS_REGION (or
+//! AWS_DEFAULT_REGION) and AWS_ENDPOINT_URL. Requests use path-style URLs and
+//! are signed with AWS Signature Version 4.
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use sqlite_loadable::{Error, Result};
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Objects are streamed with ranged GETs of this many bytes.
+const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Splits "s3://bucket/key" into its bucket and key.
+fn parse_s3_url(url: &str) -> Result<(&str, &str)> {
+    url.strip_prefix("s3://")
+        .and_then(|rest| rest.split_once('/'))
+        .filter(|(bucket, _)| !bucket.is_empty())
+        .ok_or_else(|| {
+            Error::new_message(format!("Invalid S3 URL {url}, expected s3://bucket/key"))
+        })
+}
+
+struct Config {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    region: String,
+    /// like "https://s3.us-east-1.amazonaws.com" or "http://localhost:9000"
+    endpoint: String,
+}
+
+impl Config {
+    fn from_env() -> Result<Config> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let access_key_id = var("AWS_ACCESS_KEY_ID").ok_or_else(|| {
+            Error::new_message("AWS_ACCESS_KEY_ID must be set to read s3:// paths")
+        })?;
+        let secret_access_key = var("AWS_SECRET_ACCESS_KEY").ok_or_else(|| {
+            Error::new_message("AWS_SECRET_ACCESS_KEY must be set to read s3:// paths")
+        })?;
+        let region = var("AWS_REGION")
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| String::from("us-east-1"));
+        let endpoint = var("AWS_ENDPOINT_URL")
+            .unwrap_or_else(|| format!("https://s3.{region}.amazonaws.com"))
+            .trim_end_matches('/')
+            .to_owned();
+        Ok(Config {
+            access_key_id,
+            secret_access_key,
+            session_token: var("AWS_SESSION_TOKEN"),
+            region,
+            endpoint,
+        })
+    }
+
+    /// A signed GET request for `key` of `bucket` (or the bucket itself when
+    /// `key` is empty), with the given query parameters.
+    fn get(&self, bucket: &str, key: &str, query: &[(&str, &str)]) -> ureq::Request {
+        let path = if key.is_empty() {
+            format!("/{}", uri_encode(bucket, false))
+        } else {
+            format!("/{}/{}", uri_encode(bucket, false), uri_encode(key, false))
+        };
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<String>>()
+            .join("&");
+        let host = self
+            .endpoint
+            .split_once("://")
+            .map_or(self.endpoint.as_str(), |(_, rest)| rest);
+
+        let (date, time) = utc_now();
+        let amz_date = format!("{date}T{time}Z");
+        let mut headers = vec![
+            ("host", host.to_owned()),
+            ("x-amz-content-sha256", String::from("UNSIGNED-PAYLOAD")),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{value}\n"))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<&str>>()
+            .join(";");
+        let canonical_request = format!(
+            "GET\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\nUNSIGNED-PAYLOAD"
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = [date.as_str(), self.region.as_str(), "s3", "aws4_request"]
+            .iter()
+            .fold(
+                format!("AWS4{}", self.secret_access_key).into_bytes(),
+                |key, part| hmac_sha256(&key, part.as_bytes()),
+            );
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        let url = if query.is_empty() {
+            format!("{}{path}", self.endpoint)
+        } else {
+            format!("{}{path}?{query}", self.endpoint)
+        };
+        let mut request = ureq::get(&url).set(
+            "Authorization",
+            &format!(
+                "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                self.access_key_id
+            ),
+        );
+        for (name, value) in &headers {
+            if *name != "host" {
+                request = request.set(name, value);
+            }
+        }
+        request
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Percent-encodes everything except unreserved characters, and '/' unless
+/// `encode_slash`, as SigV4 canonical requests require.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if !encode_slash => String::from("/"),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// The current UTC date and time as "YYYYMMDD" and "HHMMSS".
+fn utc_now() -> (String, String) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // days since 1970-01-01 to a civil date, from Howard Hinnant's algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        format!("{year:04}{month:02}{day:02}"),
+        format!(
+            "{:02}{:02}{:02}",
+            secs / 3600,
+            (secs % 3600) / 60,
+            secs % 60
+        ),
+    )
+}
+
+fn request_error(url: &str, e: ureq::Error) -> Error {
+    Error::new_message(format!("Error requesting {url}: {e}"))
+}
+
+/// The text of every `<tag>` element in `xml`, with entities decoded.
+fn xml_elements(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{tag}>"), format!("</{tag}>"));
+    xml.split(open.as_str())
+        .skip(1)
+        .filter_map(|rest| rest.split_once(close.as_str()).map(|(text, _)| text))
+        .map(|text| {
+            text.replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&")
+        })
+        .collect()
+}
+
+/// The s3:// URLs of every object matching `pattern`, like
+/// "s3://bucket/logs/2023-*.csv.gz", in key order. The pattern's glob
+/// characters are matched against keys listed under the prefix before them.
+pub fn list(pattern: &str) -> Result<Vec<String>> {
+    let (bucket, key_pattern) = parse_s3_url(pattern)?;
+    let prefix = &key_pattern[..key_pattern
+        .find(|c| matches!(c, '*' | '?' | '['))
+        .unwrap_or(key_pattern.len())];
+    let matcher = glob::Pattern::new(key_pattern)
+        .map_err(|e| Error::new_message(format!("Invalid glob pattern for {pattern}: {e}")))?;
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+
+    let config = Config::from_env()?;
+    let mut urls = vec![];
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+        if let Some(token) = &continuation_token {
+            query.push(("continuation-token", token.as_str()));
+        }
+        let body = config
+            .get(bucket, "", &query)
+            .call()
+            .map_err(|e| request_error(pattern, e))?
+            .into_string()
+            .map_err(|e| Error::new_message(format!("Error listing {pattern}: {e}")))?;
+        for contents in xml_elements(&body, "Contents") {
+            if let Some(key) = xml_elements(&contents, "Key").into_iter().next() {
+                if matcher.matches_with(&key, options) {
+                    urls.push(format!("s3://{bucket}/{key}"));
+                }
+            }
+        }
+        continuation_token = xml_elements(&body, "NextContinuationToken")
+            .into_iter()
+            .next();
+        let truncated = xml_elements(&body, "IsTruncated")
+            .first()
+            .map(String::as_str)
+            == Some("true");
+        if !truncated || continuation_token.is_none() {
+            break;
+        }
+    }
+    urls.sort();
+    Ok(urls)
+}
+
+/// Streams an object with ranged GETs, so large objects are read in chunks
+/// instead of one long-lived response.
+pub struct S3Reader {
+    config: Config,
+    url: String,
+    bucket: String,
+    key: String,
+    /// offset of the next chunk to request
+    offset: u64,
+    chunk: Option<Box<dyn Read + Send + Sync>>,
+    eof: bool,
+}
+
+impl S3Reader {
+    pub fn open(url: &str) -> Result<S3Reader> {
+        let (bucket, key) = parse_s3_url(url)?;
+        let mut reader = S3Reader {
+            config: Config::from_env()?,
+            url: url.to_owned(),
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            offset: 0,
+            chunk: None,
+            eof: false,
+        };
+        // request the first chunk now, so a missing object is reported when
+        // it's opened instead of on the first read
+        reader
+            .next_chunk()
+            .map_err(|e| Error::new_message(format!("Error requesting {e}")))?;
+        Ok(reader)
+    }
+
+    fn next_chunk(&mut self) -> std::io::Result<()> {
+        let range = format!("bytes={}-{}", self.offset, self.offset + CHUNK_SIZE - 1);
+        match self
+            .config
+            .get(&self.bucket, &self.key, &[])
+            .set("Range", &range)
+            .call()
+        {
+            Ok(response) => {
+                // servers that ignore Range send the whole object with a 200
+                let length: Option<u64> = response
+                    .header("Content-Length")
+                    .and_then(|length| length.parse().ok());
+                if response.status() != 206 || length.map_or(true, |length| length < CHUNK_SIZE) {
+                    self.eof = true;
+                }
+                self.offset += CHUNK_SIZE;
+                self.chunk = Some(response.into_reader());
+                Ok(())
+            }
+            // the offset is past the end of the object
+            Err(ureq::Error::Status(416, _)) => {
+                self.eof = true;
+                self.chunk = None;
+                Ok(())
+            }
+            Err(e) => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("{}: {e}", self.url),
+            )),
+        }
+    }
+}
+
+impl Read for S3Reader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(chunk) = self.chunk.as_mut() {
+                let n = chunk.read(buf)?;
+                if n > 0 || buf.is_empty() {
+                    return Ok(n);
+                }
+                self.chunk = None;
+            }
+            if self.eof {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! A small wrapper around prepared statements, for the few places where
+//! sqlite-xsv needs to run SQL on the connection it was loaded into
+//! (ex. reading `pragma_table_info` for the `schema=` option, the rows
+//! of csv_export(), or inserting rows in csv_import()).
+
+use sqlite_loadable::ext::{
+    sqlite3ext_bind_null, sqlite3ext_bind_text, sqlite3ext_column_count, sqlite3ext_column_name,
+    sqlite3ext_column_value, sqlite3ext_errmsg, sqlite3ext_finalize, sqlite3ext_prepare_v2,
+    sqlite3ext_reset, sqlite3ext_step,
+};
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{Error, Result};
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+
+const SQLITE_OK: i32 = 0;
+const SQLITE_ROW: i32 = 100;
+const SQLITE_DONE: i32 = 101;
+
+pub struct Statement {
+    db: *mut sqlite3,
+    stmt: *mut sqlite3_stmt,
+    sql: String,
+}
+
+/// The message of the most recent error on `db`, from sqlite3_errmsg().
+fn error_message(db: *mut sqlite3) -> String {
+    let message = unsafe { sqlite3ext_errmsg(db) };
+    if message.is_null() {
+        return "unknown error".to_owned();
+    }
+    unsafe { CStr::from_ptr(message) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl Statement {
+    pub fn prepare(db: *mut sqlite3, sql: &str) -> Result<Statement> {
+        let c_sql = CString::new(sql)
+            .map_err(|_| Error::new_message("Internal sqlite-xsv error: NUL byte in SQL"))?;
+        let mut stmt: *mut sqlite3_stmt = ptr::null_mut();
+        let rc =
+            unsafe { sqlite3ext_prepare_v2(db, c_sql.as_ptr(), -1, &mut stmt, ptr::null_mut()) };
+        if rc != SQLITE_OK || stmt.is_null() {
+            let message = error_message(db);
+            unsafe { sqlite3ext_finalize(stmt) };
+            return Err(Error::new_message(format!(
+                "Error preparing \"{sql}\": {message} (result code {rc})"
+            )));
+        }
+        Ok(Statement {
+            db,
+            stmt,
+            sql: sql.to_owned(),
+        })
+    }
+
+    /// Bind `value` to the 1-based parameter `idx`.
+    pub fn bind_text(&mut self, idx: i32, value: &str) -> Result<()> {
+        let rc = unsafe {
+            sqlite3ext_bind_text(
+                self.stmt,
+                idx,
+                value.as_ptr() as *const c_char,
+                value.len() as i32,
+            )
+        };
+        if rc != SQLITE_OK {
+            return Err(Error::new_message(format!(
+                "Error binding parameter {idx} on \"{}\": {} (result code {rc})",
+                self.sql,
+                error_message(self.db)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Bind NULL to the 1-based parameter `idx`.
+    pub fn bind_null(&mut self, idx: i32) -> Result<()> {
+        let rc = unsafe { sqlite3ext_bind_null(self.stmt, idx) };
+        if rc != SQLITE_OK {
+            return Err(Error::new_message(format!(
+                "Error binding parameter {idx} on \"{}\": {} (result code {rc})",
+                self.sql,
+                error_message(self.db)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Reset the statement so it can be stepped again, keeping its bindings.
+    pub fn reset(&mut self) {
+        unsafe {
+            sqlite3ext_reset(self.stmt);
+        }
+    }
+
+    /// Prepare and run `sql` to completion, for statements that don't return rows.
+    pub fn execute(db: *mut sqlite3, sql: &str) -> Result<()> {
+        let mut stmt = Statement::prepare(db, sql)?;
+        while stmt.step()? {}
+        Ok(())
+    }
+
+    /// Step the statement, returning true if a new row is available.
+    pub fn step(&mut self) -> Result<bool> {
+        match unsafe { sqlite3ext_step(self.stmt) } {
+            SQLITE_ROW => Ok(true),
+            SQLITE_DONE => Ok(false),
+            rc => Err(Error::new_message(format!(
+                "Error executing \"{}\": {} (result code {rc})",
+                self.sql,
+                error_message(self.db)
+            ))),
+        }
+    }
+
+    /// Number of columns in the statement's result set.
+    pub fn column_count(&self) -> usize {
+        unsafe { sqlite3ext_column_count(self.stmt) as usize }
+    }
+
+    /// The name of result column `idx`, as given by its "AS" clause if any.
+    pub fn column_name(&self, idx: usize) -> Result<String> {
+        let name = unsafe { sqlite3ext_column_name(self.stmt, idx as i32) };
+        if name.is_null() {
+            return Err(Error::new_message(format!(
+                "Error reading the name of column {idx} on \"{}\"",
+                self.sql
+            )));
+        }
+        Ok(unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned())
+    }
+
+    /// The value of column `idx` in the current row. Only valid until the
+    /// next call to `step()`.
+    pub fn column_value(&self, idx: usize) -> *mut sqlite3_value {
+        unsafe { sqlite3ext_column_value(self.stmt, idx as i32) }
+    }
+}
+
+impl Drop for Statement {
+    fn drop(&mut self) {
+        unsafe {
+            sqlite3ext_finalize(self.stmt);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Typed accessors for the "row" values of xsv_rows: xsv_int(), xsv_real()
+//! and xsv_date(). Each takes the same selector as xsv_at() and an optional
+//! JSON object of options, like `xsv_real(row, 'amount', '{"thousands": ","}')`.
+
+use sqlite_loadable::api::ValueType;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::{api, Error, Result};
+
+use crate::{RowPointer, ROW_POINTER_NAME};
+
+/// What to return when a field can't be parsed as the requested type.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum OnError {
+    Null,
+    Error,
+}
+
+struct TypedOptions {
+    thousands: Option<char>,
+    decimal: char,
+    format: String,
+    on_error: OnError,
+    case_insensitive: bool,
+}
+
+impl Default for TypedOptions {
+    fn default() -> Self {
+        TypedOptions {
+            thousands: None,
+            decimal: '.',
+            format: String::from("%Y-%m-%d"),
+            on_error: OnError::Null,
+            case_insensitive: false,
+        }
+    }
+}
+
+impl TypedOptions {
+    fn parse(function: &str, options: &str) -> Result<TypedOptions> {
+        let options: serde_json::Value = serde_json::from_str(options).map_err(|e| {
+            Error::new_message(format!("{function}() options must be valid JSON: {e}"))
+        })?;
+        let options = options.as_object().ok_or_else(|| {
+            Error::new_message(format!(
+                "{function}() options must be a JSON object, like '{{\"thousands\": \",\"}}'"
+            ))
+        })?;
+        let mut result = TypedOptions::default();
+        for (key, value) in options {
+            match key.as_str() {
+                "thousands" => result.thousands = Some(option_char(function, key, value)?),
+                "decimal" => result.decimal = option_char(function, key, value)?,
+                "format" => {
+                    result.format = value
+                        .as_str()
+                        .ok_or_else(|| {
+                            Error::new_message(format!("{function}() format must be a string"))
+                        })?
+                        .to_owned();
+                    validate_date_format(function, &result.format)?;
+                }
+                "on_error" => {
+                    result.on_error = match value.as_str() {
+                        Some("null") => OnError::Null,
+                        Some("error") => OnError::Error,
+                        _ => {
+                            return Err(Error::new_message(format!(
+                                "{function}() on_error must be 'null' or 'error'"
+                            )))
+                        }
+                    }
+                }
+                "case_insensitive" => {
+                    result.case_insensitive = match value {
+                        serde_json::Value::Bool(b) => *b,
+                        serde_json::Value::Number(n) => n.as_i64() != Some(0),
+                        _ => {
+                            return Err(Error::new_message(format!(
+                                "{function}() case_insensitive must be a boolean"
+                            )))
+                        }
+                    }
+                }
+                _ => {
+                    return Err(Error::new_message(format!(
+                        "Unknown {function}() option '{key}'"
+                    )))
+                }
+            }
+        }
+        if result.thousands == Some(result.decimal) {
+            return Err(Error::new_message(format!(
+                "{function}() thousands and decimal separators must be different"
+            )));
+        }
+        Ok(result)
+    }
+
+    /// `field` with thousands separators removed and the decimal separator
+    /// replaced with '.', ready for Rust's number parsing.
+    fn normalize_number(&self, field: &str) -> String {
+        field
+            .trim()
+            .chars()
+            .filter(|c| Some(*c) != self.thousands)
+            .map(|c| if c == self.decimal { '.' } else { c })
+            .collect()
+    }
+}
+
+/// A single-character option value, like "thousands": ",".
+fn option_char(function: &str, key: &str, value: &serde_json::Value) -> Result<char> {
+    let mut chars = value.as_str().unwrap_or_default().chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(Error::new_message(format!(
+            "{function}() {key} must be a single character"
+        ))),
+    }
+}
+
+/// Resolves the field for a typed accessor. Returns None when the result should
+/// be NULL: a missing or empty field.
+fn typed_field<'a>(
+    function: &str,
+    values: &'a [*mut sqlite3_value],
+) -> Result<(Option<&'a str>, TypedOptions)> {
+    let options = match values.get(2) {
+        Some(value) if !matches!(api::value_type(value), ValueType::Null) => {
+            TypedOptions::parse(function, api::value_text(value)?)?
+        }
+        _ => TypedOptions::default(),
+    };
+    let record = unsafe { api::value_pointer::<RowPointer>(&values[0], ROW_POINTER_NAME) }
+        .ok_or_else(|| {
+            Error::new_message(format!("{function}() expects a row value from xsv_rows"))
+        })?;
+    let field = unsafe { &*record }.field(function, &values[1], options.case_insensitive)?;
+    Ok((field.filter(|f| !f.trim().is_empty()), options))
+}
+
+/// Reports a field that couldn't be parsed, either as NULL or as an error.
+fn invalid(
+    context: *mut sqlite3_context,
+    options: &TypedOptions,
+    function: &str,
+    field: &str,
+    expected: &str,
+) -> Result<()> {
+    match options.on_error {
+        OnError::Null => {
+            api::result_null(context);
+            Ok(())
+        }
+        OnError::Error => Err(Error::new_message(format!(
+            "{function}(): '{field}' is not a valid {expected}"
+        ))),
+    }
+}
+
+/// xsv_int(row, selector, [options])
+pub fn xsv_int(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let (field, options) = typed_field("xsv_int", values)?;
+    let field = match field {
+        Some(field) => field,
+        None => {
+            api::result_null(context);
+            return Ok(());
+        }
+    };
+    match options.normalize_number(field).parse::<i64>() {
+        Ok(value) => api::result_int64(context, value),
+        Err(_) => return invalid(context, &options, "xsv_int", field, "integer"),
+    }
+    Ok(())
+}
+
+/// Parses a REAL field. "inf" and "NaN" parse as floats, but aren't numbers in
+/// a CSV. Also used when csv_import() infers column types.
+pub fn parse_real(field: &str) -> Option<f64> {
+    field.parse::<f64>().ok().filter(|value| value.is_finite())
+}
+
+/// xsv_real(row, selector, [options])
+pub fn xsv_real(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let (field, options) = typed_field("xsv_real", values)?;
+    let field = match field {
+        Some(field) => field,
+        None => {
+            api::result_null(context);
+            return Ok(());
+        }
+    };
+    match parse_real(&options.normalize_number(field)) {
+        Some(value) => api::result_double(context, value),
+        None => return invalid(context, &options, "xsv_real", field, "real"),
+    }
+    Ok(())
+}
+
+/// xsv_date(row, selector, [options]): parses the field with the "format" option
+/// (default '%Y-%m-%d') and returns it as an ISO 8601 'YYYY-MM-DD' date.
+pub fn xsv_date(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let (field, options) = typed_field("xsv_date", values)?;
+    let field = match field {
+        Some(field) => field,
+        None => {
+            api::result_null(context);
+            return Ok(());
+        }
+    };
+    match parse_date(field.trim(), &options.format) {
+        Some((year, month, day)) => {
+            api::result_text(context, format!("{year:04}-{month:02}-{day:02}").as_str())?
+        }
+        None => {
+            let expected = format!("date in the format '{}'", options.format);
+            return invalid(context, &options, "xsv_date", field, &expected);
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `format` only uses the directives parse_date() supports, and
+/// has a year, month and day, so a typo'd format isn't silently NULL for
+/// every row.
+fn validate_date_format(function: &str, format: &str) -> Result<()> {
+    let (mut year, mut month, mut day) = (false, false, false);
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => year = true,
+            Some('m') => month = true,
+            Some('d') => day = true,
+            Some('%') => (),
+            Some(other) => {
+                return Err(Error::new_message(format!(
+                    "{function}(): unsupported directive '%{other}' in format, expected %Y, %m, %d or %%"
+                )))
+            }
+            None => {
+                return Err(Error::new_message(format!(
+                    "{function}(): format can't end with a single '%'"
+                )))
+            }
+        }
+    }
+    if !(year && month && day) {
+        return Err(Error::new_message(format!(
+            "{function}(): format '{format}' must include %Y, %m and %d"
+        )));
+    }
+    Ok(())
+}
+
+/// Parses `value` as a date in `format`, which supports %Y (4 digit year),
+/// %m (month), %d (day) and %% (a literal '%'). All other characters match
+/// themselves. Returns None if the value doesn't match or isn't a real date.
+fn parse_date(value: &str, format: &str) -> Option<(u32, u32, u32)> {
+    let (mut year, mut month, mut day) = (None, None, None);
+    let mut value = value;
+    let mut format = format.chars();
+    while let Some(c) = format.next() {
+        if c != '%' {
+            value = value.strip_prefix(c)?;
+            continue;
+        }
+        let (target, max_digits) = match format.next()? {
+            'Y' => (&mut year, 4),
+            'm' => (&mut month, 2),
+            'd' => (&mut day, 2),
+            '%' => {
+                value = value.strip_prefix('%')?;
+                continue;
+            }
+            _ => return None,
+        };
+        let digits = value
+            .chars()
+            .take(max_digits)
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits == 0 || (max_digits == 4 && digits != 4) {
+            return None;
+        }
+        *target = Some(value[..digits].parse::<u32>().ok()?);
+        value = &value[digits..];
+    }
+    if !value.is_empty() {
+        return None;
+    }
+    let (year, month, day) = (year?, month?, day?);
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if day == 0 || day > days_in_month {
+        return None;
+    }
+    Some((year, month, day))
+}
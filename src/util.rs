@@ -1,54 +1,364 @@
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
 
+use std::cell::{Cell, RefCell};
 use std::ffi::OsStr;
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::path::Path;
+use std::rc::Rc;
 
 #[cfg(feature = "gzip_support")]
-use flate2::read::GzDecoder;
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
 
 #[cfg(feature = "zstd_support")]
-use zstd::stream::read::Decoder as ZstdDecoder;
+use zstd::stream::{read::Decoder as ZstdDecoder, write::Encoder as ZstdEncoder};
 
 use sqlite_loadable::prelude::*;
-use sqlite_loadable::{vtab_argparse::ConfigOptionValue, Error, Result};
+use sqlite_loadable::{
+    api,
+    vtab_argparse::{ColumnDeclaration, ConfigOptionValue},
+    Error, Result,
+};
 
-/// given a "path" (which can be a filepath or URL, if http_support or
-/// s3_support is enabled), return an std::io::Reader that can be passed
-/// into rust-csv ReadBuilder. Determines whether or not to use gzip
-/// decompressing  (based on file extension only for now), or whether
-/// to use sqlite-http/sqlite-s3 if a URL is supplied.
+use crate::columns::{MatchColumns, MissingColumns};
+use crate::statement::Statement;
+
+/// given a "path" (which can be a filepath, "-" for stdin, an http(s) URL if
+/// http_support is enabled, or an s3:// URL if s3_support is enabled), return
+/// an std::io::Reader that can be passed into rust-csv ReadBuilder.
+/// Determines whether or not to use gzip decompressing based on file
+/// extension, falling back to the magic bytes at the start of the file.
 pub fn get_csv_source_reader<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read>> {
+    let file = open_source_file(&path)?;
+    get_csv_file_reader(path, file)
+}
+
+/// Like get_csv_source_reader, but adds the number of bytes read from the file,
+/// before decompression, to `bytes_read`.
+pub fn get_counted_csv_source_reader<P: AsRef<Path>>(
+    path: P,
+    bytes_read: &Rc<Cell<u64>>,
+) -> Result<Box<dyn Read>> {
+    let file = open_source_file(&path)?;
+    get_csv_file_reader(path, Box::new(CountingReader::new(file, bytes_read)))
+}
+
+/// Opens the file at `path`, stdin for "-", or the body of an http(s) or s3 URL.
+fn open_source_file<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read>> {
+    if path.as_ref() == Path::new("-") {
+        return Ok(Box::new(std::io::stdin()));
+    }
+    if let Some(url) = path.as_ref().to_str().filter(|path| is_url(path)) {
+        return get_url_reader(url);
+    }
+    if let Some(url) = path.as_ref().to_str().filter(|path| is_s3_url(path)) {
+        return get_s3_reader(url);
+    }
+    Ok(Box::new(
+        File::open(path).map_err(|e| Error::new_message(e.to_string()))?,
+    ))
+}
+
+fn get_csv_file_reader<P: AsRef<Path>>(path: P, file: Box<dyn Read>) -> Result<Box<dyn Read>> {
+    // servers may or may not decode .gz files with a Content-Encoding, so
+    // URLs are always detected by their magic bytes
+    if path.as_ref().to_str().map_or(false, is_url) {
+        return get_decompressed_reader(file);
+    }
     match path.as_ref().extension().and_then(OsStr::to_str) {
-        Some(ext) => match ext {
+        #[cfg(feature = "gzip_support")]
+        Some("gz") => {
+            let r = std::io::BufReader::new(file);
+            let x = BufReader::new(MultiGzDecoder::new(r));
+            Ok(Box::new(x))
+        }
+        #[cfg(feature = "zstd_support")]
+        Some("zst") => {
+            let r = std::io::BufReader::new(file);
+            let x = BufReader::new(
+                ZstdDecoder::new(r)
+                    .map_err(|_| Error::new_message("error reading file as zstd"))?,
+            );
+            Ok(Box::new(x))
+        }
+        _ => get_decompressed_reader(file),
+    }
+}
+
+/// Whether `path` is an http:// or https:// URL, which are read with the
+/// http_support feature.
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// The body of a GET request to `url`. Responses with a gzip Content-Encoding
+/// are decoded by ureq.
+#[cfg(feature = "http_support")]
+fn get_url_reader(url: &str) -> Result<Box<dyn Read>> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| Error::new_message(format!("Error requesting {url}: {e}")))?;
+    Ok(response.into_reader())
+}
+
+#[cfg(not(feature = "http_support"))]
+fn get_url_reader(url: &str) -> Result<Box<dyn Read>> {
+    Err(Error::new_message(format!(
+        "Can't read {url}: sqlite-xsv was built without http_support"
+    )))
+}
+
+/// Whether `path` is an s3://bucket/key URL, which are read with the
+/// s3_support feature.
+pub fn is_s3_url(path: &str) -> bool {
+    path.starts_with("s3://")
+}
+
+#[cfg(feature = "s3_support")]
+fn get_s3_reader(url: &str) -> Result<Box<dyn Read>> {
+    Ok(Box::new(crate::s3::S3Reader::open(url)?))
+}
+
+#[cfg(not(feature = "s3_support"))]
+fn get_s3_reader(url: &str) -> Result<Box<dyn Read>> {
+    Err(Error::new_message(format!(
+        "Can't read {url}: sqlite-xsv was built without s3_support"
+    )))
+}
+
+/// The s3:// URLs of the objects matching an s3:// glob pattern.
+#[cfg(feature = "s3_support")]
+pub fn list_s3_urls(pattern: &str) -> Result<Vec<String>> {
+    crate::s3::list(pattern)
+}
+
+#[cfg(not(feature = "s3_support"))]
+pub fn list_s3_urls(pattern: &str) -> Result<Vec<String>> {
+    Err(Error::new_message(format!(
+        "Can't read {pattern}: sqlite-xsv was built without s3_support"
+    )))
+}
+
+/// Counts the bytes read through a reader into a shared counter, so a cursor
+/// can report how much of a (possibly compressed) source it has consumed.
+pub struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(inner: R, count: &Rc<Cell<u64>>) -> Self {
+        CountingReader {
+            inner,
+            count: Rc::clone(count),
+        }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Whether `path` is stdin ("-") or a file that can only be read once, like a
+/// named pipe or /dev/stdin, rather than a regular file.
+pub fn is_stream_path(path: &str) -> bool {
+    path == "-" || std::fs::metadata(path).map_or(false, |m| !m.is_file() && !m.is_dir())
+}
+
+/// A source that can only be read once, like stdin or a named pipe. The bytes
+/// read while sniffing its headers are kept, so the scan that follows can
+/// replay them before reading the rest of the stream.
+pub struct ReplayableStream {
+    stream: Rc<RefCell<Box<dyn Read>>>,
+    /// bytes read from the stream by sniff_reader() so far
+    head: Rc<RefCell<Vec<u8>>>,
+    scanned: bool,
+}
+
+impl ReplayableStream {
+    pub fn open(path: &str) -> Result<ReplayableStream> {
+        Ok(ReplayableStream {
+            stream: Rc::new(RefCell::new(open_source_file(path)?)),
+            head: Rc::new(RefCell::new(vec![])),
+            scanned: false,
+        })
+    }
+
+    /// A reader for sniffing headers, which replays what earlier sniffs read
+    /// and keeps everything it reads from the stream for the scan.
+    pub fn sniff_reader(&self) -> Box<dyn Read> {
+        let replay = std::io::Cursor::new(self.head.borrow().clone());
+        Box::new(replay.chain(RecordingReader {
+            stream: Rc::clone(&self.stream),
+            head: Rc::clone(&self.head),
+        }))
+    }
+
+    /// A reader for the whole stream, including anything sniffed. Only one
+    /// scan can be made, since the stream can't be rewound.
+    pub fn scan_reader(&mut self, path: &str) -> Result<Box<dyn Read>> {
+        if self.scanned {
+            return Err(Error::new_message(format!(
+                "{path} is a stream that can only be read once. Copy it into a table to query it again."
+            )));
+        }
+        self.scanned = true;
+        let head = std::io::Cursor::new(self.head.take());
+        Ok(Box::new(head.chain(SharedReader(Rc::clone(&self.stream)))))
+    }
+}
+
+struct SharedReader(Rc<RefCell<Box<dyn Read>>>);
+
+impl Read for SharedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+struct RecordingReader {
+    stream: Rc<RefCell<Box<dyn Read>>>,
+    head: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Read for RecordingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.stream.borrow_mut().read(buf)?;
+        self.head.borrow_mut().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// The writer counterpart of get_csv_source_reader: creates the file at `path`,
+/// compressing with gzip or zstd based on the file extension. With `append`,
+/// writes to the end of an existing file instead, as a new gzip member or zstd
+/// frame for compressed files.
+pub fn get_csv_destination_writer<P: AsRef<Path>>(
+    path: P,
+    append: bool,
+) -> Result<DestinationWriter> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(&path)
+        .map_err(|e| {
+            Error::new_message(format!(
+                "Error creating {}: {e}",
+                path.as_ref().to_string_lossy()
+            ))
+        })?;
+    match path.as_ref().extension().and_then(OsStr::to_str) {
+        #[cfg(feature = "gzip_support")]
+        Some("gz") => Ok(DestinationWriter::Gzip(GzEncoder::new(
+            BufWriter::new(file),
+            Compression::default(),
+        ))),
+        #[cfg(feature = "zstd_support")]
+        Some("zst") => Ok(DestinationWriter::Zstd(
+            ZstdEncoder::new(BufWriter::new(file), 0)
+                .map_err(|_| Error::new_message("error writing file as zstd"))?,
+        )),
+        _ => Ok(DestinationWriter::File(BufWriter::new(file))),
+    }
+}
+
+/// A file being written by an export, possibly compressed. Buffered and
+/// compressed data is only guaranteed to be written by finish(), since errors
+/// from dropping the writer are lost.
+pub enum DestinationWriter {
+    File(BufWriter<File>),
+    #[cfg(feature = "gzip_support")]
+    Gzip(GzEncoder<BufWriter<File>>),
+    #[cfg(feature = "zstd_support")]
+    Zstd(ZstdEncoder<'static, BufWriter<File>>),
+}
+
+impl DestinationWriter {
+    /// Writes the end of the compressed stream, if any, and flushes everything
+    /// to the file.
+    pub fn finish(self) -> std::io::Result<()> {
+        let file = match self {
+            DestinationWriter::File(writer) => writer,
             #[cfg(feature = "gzip_support")]
-            "gz" => {
-                let r = std::io::BufReader::new(
-                    File::open(path).map_err(|e| Error::new_message(e.to_string()))?,
-                );
-                let x = BufReader::new(GzDecoder::new(r));
-                Ok(Box::new(x))
-            }
+            DestinationWriter::Gzip(writer) => writer.finish()?,
             #[cfg(feature = "zstd_support")]
-            "zst" => {
-                let r = std::io::BufReader::new(
-                    File::open(path).map_err(|e| Error::new_message(e.to_string()))?,
-                );
-                let x = BufReader::new(
-                    ZstdDecoder::new(r)
-                        .map_err(|_| Error::new_message("error reading file as zstd"))?,
-                );
-                Ok(Box::new(x))
-            }
-            _ => Ok(Box::new(
-                File::open(path).map_err(|e| Error::new_message(e.to_string()))?,
-            )),
-        },
-        _ => Ok(Box::new(
-            File::open(path).map_err(|e| Error::new_message(e.to_string()))?,
-        )),
+            DestinationWriter::Zstd(writer) => writer.finish()?,
+        };
+        file.into_inner().map_err(|e| e.into_error())?;
+        Ok(())
+    }
+}
+
+impl Write for DestinationWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            DestinationWriter::File(writer) => writer.write(buf),
+            #[cfg(feature = "gzip_support")]
+            DestinationWriter::Gzip(writer) => writer.write(buf),
+            #[cfg(feature = "zstd_support")]
+            DestinationWriter::Zstd(writer) => writer.write(buf),
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            DestinationWriter::File(writer) => writer.flush(),
+            #[cfg(feature = "gzip_support")]
+            DestinationWriter::Gzip(writer) => writer.flush(),
+            #[cfg(feature = "zstd_support")]
+            DestinationWriter::Zstd(writer) => writer.flush(),
+        }
+    }
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The compression of the file at `path` that get_csv_source_reader would
+/// detect, "gzip" or "zstd": by its `.gz` or `.zst` extension, falling back to
+/// the magic bytes at the start of the file in `head`.
+pub fn detect_compression(path: &Path, head: &[u8]) -> Option<&'static str> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("gz") => Some("gzip"),
+        Some("zst") => Some("zstd"),
+        _ if head.starts_with(GZIP_MAGIC) => Some("gzip"),
+        _ if head.starts_with(ZSTD_MAGIC) => Some("zstd"),
+        _ => None,
+    }
+}
+
+/// Wraps a reader of unknown content (a BLOB, a sqlite-reader object, or a file
+/// without a known extension) with a gzip or zstd decoder if it starts with
+/// their magic bytes. Otherwise the content is read as-is.
+pub fn get_decompressed_reader(reader: Box<dyn Read>) -> Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(reader);
+    let head = reader
+        .fill_buf()
+        .map_err(|e| Error::new_message(format!("Error reading source: {e}")))?;
+    if head.starts_with(GZIP_MAGIC) {
+        #[cfg(feature = "gzip_support")]
+        return Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))));
+        #[cfg(not(feature = "gzip_support"))]
+        return Err(Error::new_message(
+            "source is gzip-compressed, but sqlite-xsv was built without gzip support",
+        ));
     }
+    if head.starts_with(ZSTD_MAGIC) {
+        #[cfg(feature = "zstd_support")]
+        return Ok(Box::new(BufReader::new(ZstdDecoder::new(reader).map_err(
+            |_| Error::new_message("error reading source as zstd"),
+        )?)));
+        #[cfg(not(feature = "zstd_support"))]
+        return Err(Error::new_message(
+            "source is zstd-compressed, but sqlite-xsv was built without zstd support",
+        ));
+    }
+    Ok(Box::new(reader))
 }
 
 /// Parse the `delimiter="|"` config option argument.
@@ -136,17 +446,225 @@ pub fn parse_filename_config_value(_db: *mut sqlite3, value: ConfigOptionValue)
 }
 
 pub fn parse_header_config_value(value: ConfigOptionValue) -> Result<bool> {
+    parse_bool_config_value("header", value)
+}
+
+/// Parse the `schema="table_or_view"` config option argument.
+pub fn parse_schema_config_value(value: ConfigOptionValue) -> Result<String> {
+    match value {
+        ConfigOptionValue::Quoted(value) | ConfigOptionValue::Bareword(value) => Ok(value),
+        _ => Err(Error::new_message(
+            "'schema' value must be a table or view name",
+        )),
+    }
+}
+
+/// Parse the `sniff="sample.csv"` config option argument, a path to a sample
+/// file whose headers column selectors are resolved against.
+pub fn parse_sniff_config_value(value: ConfigOptionValue) -> Result<String> {
+    match value {
+        ConfigOptionValue::Quoted(value) => Ok(value),
+        _ => Err(Error::new_message(
+            "'sniff' value must be a path, wrap in single or double quotes.",
+        )),
+    }
+}
+
+/// Build column declarations from the columns of an existing table or view,
+/// using `pragma_table_info`. `name` may be schema-qualified, like "main.students".
+pub fn columns_from_schema(db: *mut sqlite3, name: &str) -> Result<Vec<ColumnDeclaration>> {
+    let mut stmt = match name.split_once('.') {
+        Some((schema, table)) => {
+            let mut stmt =
+                Statement::prepare(db, "select name, type from pragma_table_info(?1, ?2)")?;
+            stmt.bind_text(1, table)?;
+            stmt.bind_text(2, schema)?;
+            stmt
+        }
+        None => {
+            let mut stmt = Statement::prepare(db, "select name, type from pragma_table_info(?1)")?;
+            stmt.bind_text(1, name)?;
+            stmt
+        }
+    };
+    let mut columns = vec![];
+    while stmt.step()? {
+        let column_name = api::value_text(&stmt.column_value(0))?.to_owned();
+        let declared_type = api::value_text(&stmt.column_value(1))?;
+        columns.push(ColumnDeclaration {
+            name: column_name,
+            declared_type: if declared_type.is_empty() {
+                None
+            } else {
+                Some(declared_type.to_owned())
+            },
+            constraints: None,
+        });
+    }
+    if columns.is_empty() {
+        return Err(Error::new_message(format!(
+            "schema table '{name}' does not exist or has no columns"
+        )));
+    }
+    Ok(columns)
+}
+
+/// Parse the `match_columns='name'` config option argument.
+pub fn parse_match_columns_config_value(value: ConfigOptionValue) -> Result<MatchColumns> {
+    match value {
+        ConfigOptionValue::Quoted(value) | ConfigOptionValue::Bareword(value) => {
+            match value.to_lowercase().as_str() {
+                "position" => Ok(MatchColumns::Position),
+                "name" => Ok(MatchColumns::Name),
+                _ => Err(Error::new_message(format!(
+                    "Unknown match_columns value '{value}', expected 'position' or 'name'"
+                ))),
+            }
+        }
+        _ => Err(Error::new_message("Unknown match_columns value")),
+    }
+}
+
+/// Parse the `missing_columns='null'` config option argument.
+pub fn parse_missing_columns_config_value(value: ConfigOptionValue) -> Result<MissingColumns> {
+    match value {
+        ConfigOptionValue::Quoted(value) | ConfigOptionValue::Bareword(value) => {
+            match value.to_lowercase().as_str() {
+                "error" => Ok(MissingColumns::Error),
+                "null" => Ok(MissingColumns::Null),
+                _ => Err(Error::new_message(format!(
+                    "Unknown missing_columns value '{value}', expected 'error' or 'null'"
+                ))),
+            }
+        }
+        _ => Err(Error::new_message("Unknown missing_columns value")),
+    }
+}
+
+/// Parse the `aliases="amount=amt|total;date=dt"` config option argument,
+/// into a list of column names and the other header names they may appear as.
+pub fn parse_aliases_config_value(value: ConfigOptionValue) -> Result<Vec<(String, Vec<String>)>> {
+    if let ConfigOptionValue::Quoted(value) = value {
+        value
+            .split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (column, aliases) = entry.split_once('=').ok_or_else(|| {
+                    Error::new_message(format!(
+                        "Invalid aliases entry '{entry}', expected 'column=alias1|alias2'"
+                    ))
+                })?;
+                Ok((
+                    column.trim().to_owned(),
+                    aliases.split('|').map(|a| a.trim().to_owned()).collect(),
+                ))
+            })
+            .collect()
+    } else {
+        Err(Error::new_message(
+            "'aliases' value must be string, wrap in single or double quotes.",
+        ))
+    }
+}
+
+/// Parse a boolean config option argument, like `union_by_name=on`.
+pub fn parse_bool_config_value(key: &str, value: ConfigOptionValue) -> Result<bool> {
     match value {
         ConfigOptionValue::Quoted(value) | ConfigOptionValue::Bareword(value) => {
             match value.to_lowercase().as_str() {
                 "yes" | "y" | "on" | "true" | "1" => Ok(true),
                 "no" | "n" | "off" | "false" | "0" => Ok(false),
-                _ => Err(Error::new_message(
-                    format!("Unknown header value '{}'", value).as_str(),
-                )),
+                _ => Err(Error::new_message(format!("Unknown {key} value '{value}'"))),
             }
         }
+        _ => Err(Error::new_message(format!("Unknown {key} value"))),
+    }
+}
 
-        _ => Err(Error::new_message("Unknown header value")),
+/// A boolean value in a JSON options object, like "header": false. Numbers and
+/// strings like "off" are also accepted.
+pub fn json_bool_option(key: &str, value: &serde_json::Value) -> Result<bool> {
+    match value {
+        serde_json::Value::Bool(b) => Ok(*b),
+        serde_json::Value::Number(n) => Ok(n.as_i64() != Some(0)),
+        serde_json::Value::String(s) => {
+            parse_bool_config_value(key, ConfigOptionValue::Bareword(s.clone()))
+        }
+        _ => Err(Error::new_message(format!("Unknown {key} value"))),
+    }
+}
+
+/// A single-character value in a JSON options object, like "quote": "'".
+pub fn json_character_option(key: &str, value: &serde_json::Value) -> Result<u8> {
+    match value.as_str().map(|s| s.as_bytes()) {
+        Some([c]) => Ok(*c),
+        _ => Err(Error::new_message(format!(
+            "'{key}' option must be a single character string"
+        ))),
+    }
+}
+
+/// How records that don't have the expected number of fields are handled.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FieldCount {
+    /// Every record in a file must have as many fields as the file's header
+    /// row (or first record). Table columns that a file doesn't have are NULL.
+    Default,
+    /// Like `Default`, but a record that doesn't have a field for every
+    /// table column is an error.
+    Strict,
+    /// Records can have any number of fields. Missing fields are NULL,
+    /// and extra fields are ignored.
+    Flexible,
+}
+
+/// Resolve the `strict=` and `flexible=` config options into a `FieldCount`.
+pub fn field_count_from_config(strict: Option<bool>, flexible: Option<bool>) -> Result<FieldCount> {
+    match (strict, flexible) {
+        (Some(true), Some(true)) => Err(Error::new_message(
+            "strict=on and flexible=on cannot be used together",
+        )),
+        (_, Some(true)) => Ok(FieldCount::Flexible),
+        (Some(true), _) => Ok(FieldCount::Strict),
+        _ => Ok(FieldCount::Default),
+    }
+}
+
+/// Error for a record with a different number of fields than expected,
+/// naming the source, line number, and expected/actual field counts.
+pub fn field_count_error(
+    source: &str,
+    position: Option<&csv::Position>,
+    expected: &str,
+    found: u64,
+) -> Error {
+    let line = position.map_or_else(|| "?".to_owned(), |p| p.line().to_string());
+    Error::new_message(format!(
+        "Error reading {source} line {line}: expected {expected} fields, found {found}. Use flexible=on to allow records with a different number of fields"
+    ))
+}
+
+/// Result a line number, byte offset or count as an INTEGER, erroring instead of
+/// wrapping around when it doesn't fit in an i64.
+pub fn result_count(context: *mut sqlite3_context, value: u64) -> Result<()> {
+    let value = i64::try_from(value)
+        .map_err(|_| Error::new_message(format!("Integer overflow: {value} is not an i64")))?;
+    api::result_int64(context, value);
+    Ok(())
+}
+
+/// Parse a non-negative integer config option argument, like `columns_limit=100`.
+pub fn parse_integer_config_value(key: &str, value: ConfigOptionValue) -> Result<usize> {
+    match value {
+        ConfigOptionValue::Quoted(value) | ConfigOptionValue::Bareword(value) => {
+            value.trim().parse::<usize>().map_err(|_| {
+                Error::new_message(format!(
+                    "'{key}' value must be a non-negative integer, got '{value}'"
+                ))
+            })
+        }
+        _ => Err(Error::new_message(format!(
+            "'{key}' value must be a non-negative integer"
+        ))),
     }
 }
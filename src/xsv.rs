@@ -8,16 +8,37 @@ use sqlite_loadable::{
 };
 use sqlite_loadable::{prelude::*, table};
 
-use glob::{glob, Paths};
+use glob::{glob, GlobResult};
+use std::cell::Cell;
 use std::ffi::c_void;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::{io::Read, mem, os::raw::c_int};
 
+/// Hidden columns declared after every other column, reporting how far a scan
+/// has read through its files.
+const PROGRESS_COLUMNS: &str =
+    ", _bytes_read hidden, _total_bytes hidden, _uncompressed_bytes_read hidden";
+
+/// The files a cursor reads, in order: the matches of a glob pattern (of local
+/// files or s3:// objects), or a single URL.
+type SourcePaths = Box<dyn Iterator<Item = GlobResult>>;
+
+use crate::columns::{union_headers, ColumnMatcher, MatchColumns, MissingColumns};
+use crate::files::{matching_files, parse_glob_order_config_value, GlobOptions};
 use crate::util::{
-    get_csv_source_reader, parse_delimiter_config_value, parse_filename_config_value,
-    parse_header_config_value, parse_quote_config_value,
+    columns_from_schema, field_count_error, field_count_from_config, get_counted_csv_source_reader,
+    get_csv_source_reader, get_decompressed_reader, is_s3_url, is_stream_path, is_url,
+    list_s3_urls, parse_aliases_config_value, parse_bool_config_value,
+    parse_delimiter_config_value, parse_filename_config_value, parse_header_config_value,
+    parse_integer_config_value, parse_match_columns_config_value,
+    parse_missing_columns_config_value, parse_quote_config_value, parse_schema_config_value,
+    result_count, CountingReader, FieldCount, ReplayableStream,
 };
 
+/// SQLite's default SQLITE_MAX_COLUMN, the most columns a table can declare.
+const SQLITE_MAX_COLUMN: usize = 2000;
+
 #[repr(C)]
 pub struct XsvTable {
     /// must be first
@@ -27,7 +48,25 @@ pub struct XsvTable {
     header: bool,
     delimiter: u8,
     quote: u8,
+    field_count: FieldCount,
     declared_columns: Option<Vec<ColumnDeclaration>>,
+    /// when match_columns='name', maps the table's columns to fields of each file
+    column_matcher: Option<ColumnMatcher>,
+    /// when union_by_name=on, the union of header names across all matched files
+    union_headers: Option<Vec<String>>,
+    /// when columns_limit=N, only the first N fields are declared as columns,
+    /// the rest are available as a JSON array in the hidden _rest column
+    columns_limit: Option<usize>,
+    /// number of columns declared from the headers or column declarations,
+    /// which with columns_limit=N is the index of the hidden _rest column
+    column_count: usize,
+    /// which files a glob pattern matches, and the order they're read in
+    glob_options: GlobOptions,
+    /// index of the hidden _bytes_read column, followed by _total_bytes and
+    /// _uncompressed_bytes_read
+    progress_column: usize,
+    /// when the input is stdin ("-") or a named pipe, which can only be read once
+    stream: Option<ReplayableStream>,
 
     // dynamically updated from a cursor's xNext. NOT threadsafe.
     current_path: String,
@@ -36,16 +75,52 @@ pub struct XsvTable {
     current_line_number: u64,
 }
 impl XsvTable {
+    fn source_paths(&self) -> Result<SourcePaths> {
+        if is_url(&self.input) {
+            return Ok(Box::new(std::iter::once(Ok(PathBuf::from(&self.input)))));
+        }
+        // object storage has no directories to glob, so keys are listed instead
+        if is_s3_url(&self.input) {
+            let urls = list_s3_urls(&self.input)?;
+            return Ok(Box::new(urls.into_iter().map(|url| Ok(PathBuf::from(url)))));
+        }
+        let files = matching_files(&self.input, &self.glob_options)?;
+        Ok(Box::new(files.into_iter().map(Ok)))
+    }
+    fn first_path(&self) -> Result<PathBuf> {
+        self.source_paths()?
+            .next()
+            .ok_or_else(|| {
+                Error::new_message(format!("No matching files found for {}", self.input))
+            })?
+            .map_err(|e| {
+                Error::new_message(format!(
+                    "Error globbing first path for {}: {}",
+                    self.input, e
+                ))
+            })
+    }
+    /// A reader for sniffing the headers of the first matched file, or of the stream.
+    fn first_reader(&self) -> Result<csv::Reader<Box<dyn Read>>> {
+        match &self.stream {
+            Some(stream) => Ok(self.csv_reader(get_decompressed_reader(stream.sniff_reader())?)),
+            None => self.reader(self.first_path()?),
+        }
+    }
     fn reader<P: AsRef<Path>>(&self, path: P) -> Result<csv::Reader<Box<dyn Read>>> {
-        let source_reader = get_csv_source_reader(path)?;
-
-        Ok(csv::ReaderBuilder::new()
+        Ok(self.csv_reader(get_csv_source_reader(path)?))
+    }
+    fn csv_reader(&self, source_reader: Box<dyn Read>) -> csv::Reader<Box<dyn Read>> {
+        csv::ReaderBuilder::new()
             .has_headers(self.header)
             .delimiter(self.delimiter)
             .quote(self.quote)
-            .from_reader(source_reader))
+            .flexible(self.field_count == FieldCount::Flexible)
+            .from_reader(source_reader)
     }
-    fn schema_from_reader(&self) -> Result<String> {
+    /// The CREATE statement of the table, and the number of columns declared
+    /// before the hidden _rest and progress columns.
+    fn schema_from_reader(&self) -> Result<(String, usize)> {
         return match &self.declared_columns {
             // if supplied, make the CREATE statement from those names
             Some(columns) => {
@@ -57,38 +132,41 @@ impl XsvTable {
                         sql.push(',');
                     }
                 }
+                sql.push_str(PROGRESS_COLUMNS);
                 sql.push(')');
-                Ok(sql)
+                Ok((sql, columns.len()))
             }
 
             // if no columns were provided, then sniff the headers from the CSV
             None => {
-                let first_match = glob(self.input.as_str())
-                    .map_err(|e| {
-                        Error::new_message(format!(
-                            "Invalid glob pattern for {}: {}",
-                            self.input, e
-                        ))
-                    })?
-                    .next()
-                    .ok_or_else(|| {
-                        Error::new_message(format!("No matching files found for {}", self.input))
-                    })?
-                    .map_err(|e| {
-                        Error::new_message(format!(
-                            "Error globbing first path for {}: {}",
-                            self.input, e
-                        ))
-                    })?;
-                let mut reader = self.reader(first_match)?;
+                let headers: Vec<String> = match &self.union_headers {
+                    Some(headers) => headers.clone(),
+                    None => self
+                        .first_reader()?
+                        .headers()
+                        .map_err(|_| Error::new_message("Error: invalid UTF8 in headers of CSV"))?
+                        .iter()
+                        .map(|h| h.to_owned())
+                        .collect(),
+                };
+                let limit = match self.columns_limit {
+                    Some(limit) => limit,
+                    None if headers.len() > SQLITE_MAX_COLUMN => {
+                        return Err(Error::new_message(format!(
+                            "{} has {} columns, more than SQLite's limit of {} columns. Use columns_limit=N to only declare the first N columns, and read the rest as a JSON array from the hidden _rest column.",
+                            self.input,
+                            headers.len(),
+                            SQLITE_MAX_COLUMN
+                        )))
+                    }
+                    None => headers.len(),
+                };
                 let mut sql = String::from("create table x(");
 
-                let headers = reader
-                    .headers()
-                    .map_err(|_| Error::new_message("Error: invalid UTF8 in headers of CSV"))?;
-                let mut it = headers.iter().peekable();
+                let mut it = headers.iter().take(limit).peekable();
 
                 let mut ci = 1;
+                let mut column_count = 0;
                 while let Some(header) = it.next() {
                     if self.header {
                         sql.push('"');
@@ -101,10 +179,15 @@ impl XsvTable {
                         sql.push(',');
                     }
                     ci += 1;
+                    column_count += 1;
                 }
+                if self.columns_limit.is_some() {
+                    sql.push_str(", _rest hidden");
+                }
+                sql.push_str(PROGRESS_COLUMNS);
 
                 sql.push(')');
-                Ok(sql)
+                Ok((sql, column_count))
             }
         };
     }
@@ -131,19 +214,97 @@ impl<'vtab> VTab<'vtab> for XsvTable {
             aux.map(|a| a.to_owned()),
             args.table_name.as_str(),
         )?;
-        let vtab = XsvTable {
+        let declared_columns = match &arguments.schema {
+            Some(schema) => Some(columns_from_schema(db, schema)?),
+            None => arguments.columns,
+        };
+        let mut vtab = XsvTable {
             base: unsafe { mem::zeroed() },
             db,
             input: arguments.filename.clone(),
             header: arguments.header,
             delimiter: arguments.delimiter,
             quote: arguments.quote,
-            declared_columns: arguments.columns,
+            field_count: arguments.field_count,
+            declared_columns,
+            column_matcher: None,
+            union_headers: None,
+            columns_limit: arguments.columns_limit,
+            column_count: 0,
+            glob_options: arguments.glob_options,
+            progress_column: 0,
+            stream: None,
             current_path: "".to_owned(),
             current_line_number: 0,
         };
+        if is_stream_path(&vtab.input) {
+            vtab.stream = Some(ReplayableStream::open(&vtab.input)?);
+        }
+        if arguments.union_by_name {
+            let mut all_headers = vec![];
+            let paths = match vtab.stream {
+                // a stream is a single "file", so its headers are the union
+                Some(_) => {
+                    all_headers.push(
+                        vtab.first_reader()?
+                            .headers()
+                            .map_err(|_| {
+                                Error::new_message("Error: invalid UTF8 in headers of CSV")
+                            })?
+                            .clone(),
+                    );
+                    None
+                }
+                None => Some(vtab.source_paths()?),
+            };
+            for path in paths.into_iter().flatten() {
+                let path = path.map_err(|e| {
+                    Error::new_message(format!("Error globbing path for {}: {}", vtab.input, e))
+                })?;
+                let mut reader = vtab.reader(&path)?;
+                let headers = reader.headers().map_err(|_| {
+                    Error::new_message(format!(
+                        "Error: invalid UTF8 in headers of {}",
+                        path.to_string_lossy()
+                    ))
+                })?;
+                all_headers.push(headers.clone());
+            }
+            if all_headers.is_empty() {
+                return Err(Error::new_message(format!(
+                    "No matching files found for {}",
+                    vtab.input
+                )));
+            }
+            let names = union_headers(&all_headers, &arguments.aliases);
+            vtab.column_matcher = Some(ColumnMatcher::new(
+                &names,
+                &arguments.aliases,
+                MissingColumns::Null,
+            ));
+            vtab.union_headers = Some(names);
+        } else if arguments.match_columns == MatchColumns::Name {
+            let names: Vec<String> = match &vtab.declared_columns {
+                Some(columns) => columns.iter().map(|c| c.name.clone()).collect(),
+                None => vtab
+                    .first_reader()?
+                    .headers()
+                    .map_err(|_| Error::new_message("Error: invalid UTF8 in headers of CSV"))?
+                    .iter()
+                    .map(|h| h.to_owned())
+                    .collect(),
+            };
+            vtab.column_matcher = Some(ColumnMatcher::new(
+                &names,
+                &arguments.aliases,
+                arguments.missing_columns,
+            ));
+        }
 
-        Ok((vtab.schema_from_reader()?, vtab))
+        let (sql, column_count) = vtab.schema_from_reader()?;
+        vtab.column_count = column_count;
+        vtab.progress_column = column_count + usize::from(vtab.columns_limit.is_some());
+        Ok((sql, vtab))
     }
     fn destroy(&self) -> Result<()> {
         Ok(())
@@ -219,24 +380,49 @@ pub struct XsvCursor {
     /// Base class. Must be first
     base: sqlite3_vtab_cursor,
     rowid: i64,
-    paths: Paths,
+    /// None when reading a stream, which is opened with the cursor
+    paths: Option<SourcePaths>,
     current_reader: Option<csv::Reader<Box<dyn Read>>>,
     current_path: Option<PathBuf>,
     current_line_number: i64,
+    /// bytes read from all files so far, before decompression
+    bytes_read: Rc<Cell<u64>>,
+    /// size of all matched files before decompression, unknown for streams
+    total_bytes: Option<u64>,
+    /// size of the files already read, after decompression
+    finished_uncompressed_bytes: u64,
     record: csv::StringRecord,
     eof: bool,
     declared_columns: Option<Vec<ColumnDeclaration>>,
+    /// field index for each column of the current file, when match_columns='name'
+    column_mapping: Option<Vec<Option<usize>>>,
     table: *mut XsvTable,
 }
 impl XsvCursor {
     fn new(table: &mut XsvTable) -> Result<XsvCursor> {
         let record = csv::StringRecord::new();
-        let paths = glob(table.input.as_str()).map_err(|e| {
-            Error::new_message(format!(
-                "Invalid input glob pattern for {}: {}",
-                table.input, e
-            ))
-        })?;
+        let (paths, total_bytes) = match table.stream {
+            Some(_) => (None, None),
+            None => {
+                // listed once, so the total is of exactly the files that are read
+                let paths: Vec<GlobResult> = table.source_paths()?.collect();
+                // a missing or unreadable file is reported when it's opened, not
+                // here. The size of a URL isn't known until it's requested.
+                let total_bytes = if is_url(&table.input) || is_s3_url(&table.input) {
+                    None
+                } else {
+                    Some(
+                        paths
+                            .iter()
+                            .filter_map(|path| std::fs::metadata(path.as_ref().ok()?).ok())
+                            .map(|metadata| metadata.len())
+                            .sum(),
+                    )
+                };
+                let paths: SourcePaths = Box::new(paths.into_iter());
+                (Some(paths), total_bytes)
+            }
+        };
         let mut cursor = XsvCursor {
             base: unsafe { mem::zeroed() },
             rowid: 0,
@@ -244,11 +430,22 @@ impl XsvCursor {
             current_path: None,
             current_reader: None,
             current_line_number: 0,
+            bytes_read: Rc::new(Cell::new(0)),
+            total_bytes,
+            finished_uncompressed_bytes: 0,
             record,
             eof: false,
             declared_columns: table.declared_columns.clone(),
+            column_mapping: None,
             table: table as *mut XsvTable,
         };
+        if let Some(stream) = table.stream.as_mut() {
+            let source = stream.scan_reader(&table.input)?;
+            let source =
+                get_decompressed_reader(Box::new(CountingReader::new(source, &cursor.bytes_read)))?;
+            let reader = table.csv_reader(source);
+            cursor.current_reader = Some(cursor.start_reader(reader, table.input.clone())?);
+        }
         cursor.next().map(|_| cursor)
     }
 
@@ -269,25 +466,87 @@ impl XsvCursor {
                         None => 0,
                     };
                 }
+                if !has_more {
+                    self.finished_uncompressed_bytes += self.uncompressed_position();
+                }
                 Ok(has_more)
             }
             Err(err) => match err.kind() {
                 csv::ErrorKind::Utf8 { pos: _, err: _ } => Err(Error::new_message(
                     "Error: UTF8 error while reading next row",
                 )),
+                csv::ErrorKind::UnequalLengths {
+                    pos,
+                    expected_len,
+                    len,
+                } => Err(field_count_error(
+                    unsafe { (*self.table).current_path.as_str() },
+                    pos.as_ref(),
+                    expected_len.to_string().as_str(),
+                    *len,
+                )),
                 _ => Err(Error::new_message(
                     format!("Error while reading next row: {}", err).as_str(),
                 )),
             },
         }
     }
-    fn next_path_reader(&mut self) -> Result<Option<csv::Reader<Box<dyn Read>>>> {
-        match self.paths.next() {
-            Some(Ok(path)) => unsafe {
-                let s = path.to_string_lossy();
-                (*self.table).current_path = s.to_string();
-                Ok(Some((*self.table).reader(path)?))
+    /// Bytes read from the current file so far, after decompression.
+    fn uncompressed_position(&self) -> u64 {
+        self.current_reader
+            .as_ref()
+            .map_or(0, |reader| reader.position().byte())
+    }
+
+    /// The hidden _bytes_read, _total_bytes and _uncompressed_bytes_read columns.
+    /// `offset` is the index after the table's progress_column.
+    fn progress_column(&self, context: *mut sqlite3_context, offset: usize) -> Result<()> {
+        let value = match offset {
+            0 => self.bytes_read.get(),
+            1 => match self.total_bytes {
+                Some(total_bytes) => total_bytes,
+                None => {
+                    api::result_null(context);
+                    return Ok(());
+                }
             },
+            2 => self.finished_uncompressed_bytes + self.uncompressed_position(),
+            _ => {
+                return Err(Error::new_message(format!(
+                    "Internal sqlite-xsv error: invalid column index {}",
+                    offset + unsafe { (*self.table).progress_column }
+                )))
+            }
+        };
+        result_count(context, value)
+    }
+
+    /// Prepares the reader of the next file (or stream) at path `s` to be read.
+    fn start_reader(
+        &mut self,
+        mut reader: csv::Reader<Box<dyn Read>>,
+        s: String,
+    ) -> Result<csv::Reader<Box<dyn Read>>> {
+        unsafe {
+            if let Some(matcher) = &(*self.table).column_matcher {
+                let headers = reader.headers().map_err(|_| {
+                    Error::new_message(format!("Error: invalid UTF8 in headers of {s}"))
+                })?;
+                self.column_mapping = Some(matcher.mapping(headers, &s)?);
+            }
+            (*self.table).current_path = s;
+        }
+        Ok(reader)
+    }
+
+    fn next_path_reader(&mut self) -> Result<Option<csv::Reader<Box<dyn Read>>>> {
+        match self.paths.as_mut().and_then(|paths| paths.next()) {
+            Some(Ok(path)) => {
+                let s = path.to_string_lossy().to_string();
+                let reader = unsafe { &*self.table }
+                    .csv_reader(get_counted_csv_source_reader(&path, &self.bytes_read)?);
+                Ok(Some(self.start_reader(reader, s)?))
+            }
             Some(Err(error)) => Err(Error::new_message(format!(
                 "Error on next glob match: {}",
                 error
@@ -336,14 +595,45 @@ impl VTabCursor for XsvCursor {
     }
 
     fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
-        let i = usize::try_from(i)
-            .map_err(|_| Error::new_message(format!("what the fuck {}", i).as_str()))?;
+        let i = usize::try_from(i).map_err(|_| {
+            Error::new_message(format!(
+                "Internal sqlite-xsv error: invalid column index {i}"
+            ))
+        })?;
+
+        let table = unsafe { &*self.table };
+        if i >= table.progress_column {
+            return self.progress_column(context, i - table.progress_column);
+        }
+
+        if table.columns_limit.is_some() && i == table.column_count {
+            let rest: Vec<serde_json::Value> = self
+                .record
+                .iter()
+                .skip(i)
+                .map(|field| serde_json::Value::String(field.to_owned()))
+                .collect();
+            return api::result_json(context, serde_json::Value::Array(rest));
+        }
 
+        let field = match &self.column_mapping {
+            Some(mapping) => mapping.get(i).copied().flatten(),
+            None => Some(i),
+        };
         // This will typically only be None when a glob pattern is used, and the 1st sniffed CSV
-        // has more column than another CSV in the same glob pattern.
-        // For now we just return NULL for missing columns, not sure how flexible we should be
-        // across CSV files. If it's a single file, i'm pretty sure it's not flexible
-        let value = &self.record.get(i);
+        // has more column than another CSV in the same glob pattern, or with flexible=on.
+        // Those are NULL, unless strict=on.
+        let value = field.and_then(|field| self.record.get(field));
+        if let (None, Some(field)) = (value, field) {
+            if unsafe { (*self.table).field_count } == FieldCount::Strict {
+                return Err(field_count_error(
+                    unsafe { (*self.table).current_path.as_str() },
+                    self.record.position(),
+                    format!("at least {}", field + 1).as_str(),
+                    self.record.len() as u64,
+                ));
+            }
+        }
 
         if let Some(value) = value {
             match self.declared_columns.as_ref().and_then(|c| c.get(i)) {
@@ -367,6 +657,14 @@ struct XsvArguments {
     delimiter: u8,
     quote: u8,
     columns: Option<Vec<ColumnDeclaration>>,
+    schema: Option<String>,
+    match_columns: MatchColumns,
+    missing_columns: MissingColumns,
+    aliases: Vec<(String, Vec<String>)>,
+    union_by_name: bool,
+    field_count: FieldCount,
+    columns_limit: Option<usize>,
+    glob_options: GlobOptions,
 }
 
 fn parse_xsv_arguments(
@@ -380,6 +678,15 @@ fn parse_xsv_arguments(
     let mut delimiter = initial_delimiter;
     let mut quote = b'"';
     let mut columns = vec![];
+    let mut schema = None;
+    let mut match_columns = MatchColumns::Position;
+    let mut missing_columns = MissingColumns::Error;
+    let mut aliases = vec![];
+    let mut union_by_name = false;
+    let mut strict = None;
+    let mut flexible = None;
+    let mut columns_limit = None;
+    let mut glob_options = GlobOptions::default();
     for arg in arguments {
         match parse_argument(arg.as_str()) {
             Ok(arg) => match arg {
@@ -397,12 +704,95 @@ fn parse_xsv_arguments(
                     "quote" => {
                         quote = parse_quote_config_value(config.value)?;
                     }
+                    "schema" => {
+                        schema = Some(parse_schema_config_value(config.value)?);
+                    }
+                    "match_columns" => {
+                        match_columns = parse_match_columns_config_value(config.value)?;
+                    }
+                    "missing_columns" => {
+                        missing_columns = parse_missing_columns_config_value(config.value)?;
+                    }
+                    "aliases" => {
+                        aliases = parse_aliases_config_value(config.value)?;
+                    }
+                    "union_by_name" => {
+                        union_by_name = parse_bool_config_value("union_by_name", config.value)?;
+                    }
+                    "strict" => {
+                        strict = Some(parse_bool_config_value("strict", config.value)?);
+                    }
+                    "flexible" => {
+                        flexible = Some(parse_bool_config_value("flexible", config.value)?);
+                    }
+                    "columns_limit" => {
+                        columns_limit =
+                            Some(parse_integer_config_value("columns_limit", config.value)?);
+                    }
+                    "glob_order" => {
+                        glob_options.order = parse_glob_order_config_value(config.value)?;
+                    }
+                    "glob_case_insensitive" => {
+                        glob_options.case_insensitive =
+                            parse_bool_config_value("glob_case_insensitive", config.value)?;
+                    }
+                    "glob_hidden" => {
+                        glob_options.hidden = parse_bool_config_value("glob_hidden", config.value)?;
+                    }
+                    "follow_symlinks" => {
+                        glob_options.follow_symlinks =
+                            parse_bool_config_value("follow_symlinks", config.value)?;
+                    }
+                    "skip_unreadable" => {
+                        glob_options.skip_unreadable =
+                            parse_bool_config_value("skip_unreadable", config.value)?;
+                    }
                     _ => (),
                 },
             },
             Err(err) => return Err(Error::new_message(err.as_str())),
         };
     }
+    if schema.is_some() && !columns.is_empty() {
+        return Err(Error::new_message(
+            "'schema' cannot be used alongside column declarations",
+        ));
+    }
+    if match_columns == MatchColumns::Name && !header {
+        return Err(Error::new_message(
+            "match_columns='name' requires a header row, but header is off",
+        ));
+    }
+    let field_count = field_count_from_config(strict, flexible)?;
+    if union_by_name {
+        if !header {
+            return Err(Error::new_message(
+                "union_by_name=on requires a header row, but header is off",
+            ));
+        }
+        if schema.is_some() || !columns.is_empty() {
+            return Err(Error::new_message(
+                "union_by_name=on cannot be used alongside column declarations or 'schema'",
+            ));
+        }
+    }
+    if let Some(limit) = columns_limit {
+        if limit == 0 || limit >= SQLITE_MAX_COLUMN {
+            return Err(Error::new_message(format!(
+                "columns_limit must be between 1 and {}",
+                SQLITE_MAX_COLUMN - 1
+            )));
+        }
+        if schema.is_some()
+            || !columns.is_empty()
+            || union_by_name
+            || match_columns == MatchColumns::Name
+        {
+            return Err(Error::new_message(
+                "columns_limit can only be used when columns are sniffed from the first file, without union_by_name or match_columns='name'",
+            ));
+        }
+    }
     let filename = match filename {
         Some(filename) => Ok(filename),
         None => {
@@ -428,6 +818,14 @@ fn parse_xsv_arguments(
         delimiter,
         quote,
         columns,
+        schema,
+        match_columns,
+        missing_columns,
+        aliases,
+        union_by_name,
+        field_count,
+        columns_limit,
+        glob_options,
     })
 }
 
@@ -450,10 +848,148 @@ mod tests {
                 delimiter: b',',
                 quote: b'"',
                 columns: None,
+                schema: None,
+                match_columns: MatchColumns::Position,
+                missing_columns: MissingColumns::Error,
+                aliases: vec![],
+                union_by_name: false,
+                field_count: FieldCount::Default,
+                columns_limit: None,
+                glob_options: GlobOptions::default(),
             })
         );
     }
     #[test]
+    fn test_schema() {
+        assert_eq!(
+            parse_xsv_arguments(
+                std::ptr::null_mut(),
+                vec![
+                    "filename='a.csv'".to_string(),
+                    "schema=students".to_string()
+                ],
+                Some(b','),
+                "table_name"
+            ),
+            Ok(XsvArguments {
+                filename: "a.csv".to_string(),
+                header: true,
+                delimiter: b',',
+                quote: b'"',
+                columns: None,
+                schema: Some("students".to_string()),
+                match_columns: MatchColumns::Position,
+                missing_columns: MissingColumns::Error,
+                aliases: vec![],
+                union_by_name: false,
+                field_count: FieldCount::Default,
+                columns_limit: None,
+                glob_options: GlobOptions::default(),
+            })
+        );
+        assert_eq!(
+            parse_xsv_arguments(
+                std::ptr::null_mut(),
+                vec![
+                    "filename='a.csv'".to_string(),
+                    "schema='main.students'".to_string(),
+                    "a int".to_string(),
+                ],
+                Some(b','),
+                "table_name"
+            ),
+            Err(Error::new(ErrorKind::Message(
+                "'schema' cannot be used alongside column declarations".to_string()
+            )))
+        );
+    }
+    #[test]
+    fn test_match_columns() {
+        assert_eq!(
+            parse_xsv_arguments(
+                std::ptr::null_mut(),
+                vec![
+                    "filename='a.csv'".to_string(),
+                    "match_columns=name".to_string(),
+                    "missing_columns=null".to_string(),
+                    "aliases='amount=amt|total; date=dt'".to_string(),
+                ],
+                Some(b','),
+                "table_name"
+            ),
+            Ok(XsvArguments {
+                filename: "a.csv".to_string(),
+                header: true,
+                delimiter: b',',
+                quote: b'"',
+                columns: None,
+                schema: None,
+                match_columns: MatchColumns::Name,
+                missing_columns: MissingColumns::Null,
+                aliases: vec![
+                    (
+                        "amount".to_string(),
+                        vec!["amt".to_string(), "total".to_string()]
+                    ),
+                    ("date".to_string(), vec!["dt".to_string()]),
+                ],
+                union_by_name: false,
+                field_count: FieldCount::Default,
+                columns_limit: None,
+                glob_options: GlobOptions::default(),
+            })
+        );
+        assert_eq!(
+            parse_xsv_arguments(
+                std::ptr::null_mut(),
+                vec![
+                    "filename='a.csv'".to_string(),
+                    "match_columns=name".to_string(),
+                    "header=off".to_string(),
+                ],
+                Some(b','),
+                "table_name"
+            ),
+            Err(Error::new(ErrorKind::Message(
+                "match_columns='name' requires a header row, but header is off".to_string()
+            )))
+        );
+    }
+    #[test]
+    fn test_field_count() {
+        let field_count = |args: Vec<&str>| {
+            parse_xsv_arguments(
+                std::ptr::null_mut(),
+                args.iter().map(|a| a.to_string()).collect(),
+                Some(b','),
+                "table_name",
+            )
+            .map(|arguments| arguments.field_count)
+        };
+        assert_eq!(
+            field_count(vec!["filename='a.csv'"]),
+            Ok(FieldCount::Default)
+        );
+        assert_eq!(
+            field_count(vec!["filename='a.csv'", "strict=on"]),
+            Ok(FieldCount::Strict)
+        );
+        assert_eq!(
+            field_count(vec!["filename='a.csv'", "flexible=yes"]),
+            Ok(FieldCount::Flexible)
+        );
+        assert_eq!(
+            field_count(vec!["filename='a.csv'", "strict=off"]),
+            Ok(FieldCount::Default)
+        );
+        assert_eq!(
+            field_count(vec!["filename='a.csv'", "strict=on", "flexible=on"]),
+            Err(Error::new(ErrorKind::Message(
+                "strict=on and flexible=on cannot be used together".to_string()
+            )))
+        );
+    }
+    #[test]
     fn test_delimiter() {
         assert_eq!(
             parse_xsv_arguments(
@@ -468,6 +1004,14 @@ mod tests {
                 delimiter: b',',
                 quote: b'"',
                 columns: None,
+                schema: None,
+                match_columns: MatchColumns::Position,
+                missing_columns: MissingColumns::Error,
+                aliases: vec![],
+                union_by_name: false,
+                field_count: FieldCount::Default,
+                columns_limit: None,
+                glob_options: GlobOptions::default(),
             })
         );
         assert_eq!(
@@ -498,6 +1042,14 @@ mod tests {
                         constraints: None
                     }
                 ]),
+                schema: None,
+                match_columns: MatchColumns::Position,
+                missing_columns: MissingColumns::Error,
+                aliases: vec![],
+                union_by_name: false,
+                field_count: FieldCount::Default,
+                columns_limit: None,
+                glob_options: GlobOptions::default(),
             })
         );
         assert_eq!(
@@ -517,6 +1069,14 @@ mod tests {
                 delimiter: b'|',
                 quote: b'x',
                 columns: None,
+                schema: None,
+                match_columns: MatchColumns::Position,
+                missing_columns: MissingColumns::Error,
+                aliases: vec![],
+                union_by_name: false,
+                field_count: FieldCount::Default,
+                columns_limit: None,
+                glob_options: GlobOptions::default(),
             })
         );
         assert_eq!(
@@ -536,6 +1096,14 @@ mod tests {
                 delimiter: b'|',
                 quote: b'\0',
                 columns: None,
+                schema: None,
+                match_columns: MatchColumns::Position,
+                missing_columns: MissingColumns::Error,
+                aliases: vec![],
+                union_by_name: false,
+                field_count: FieldCount::Default,
+                columns_limit: None,
+                glob_options: GlobOptions::default(),
             })
         );
         assert_eq!(
@@ -562,6 +1130,14 @@ mod tests {
                 delimiter: b'\t',
                 quote: b'"',
                 columns: None,
+                schema: None,
+                match_columns: MatchColumns::Position,
+                missing_columns: MissingColumns::Error,
+                aliases: vec![],
+                union_by_name: false,
+                field_count: FieldCount::Default,
+                columns_limit: None,
+                glob_options: GlobOptions::default(),
             })
         );
         assert_eq!(
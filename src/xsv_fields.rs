@@ -1,33 +1,51 @@
 /**
- * A SQLite virtual table that yields each "field" in a given CSV record.
+ * A SQLite virtual table that yields each "field" in a given CSV record,
+ * along with its header name and index.
  *
  * ```
- * select * from csv_fields
+ * select idx, header, contents from csv_fields(row)
  * ```
  *
+ * Overloads the "->>" operator on the hidden "record" column as an alias for `xsv_at()`.
  */
 use sqlite_loadable::prelude::*;
+use sqlite_loadable::table::VTabFind;
 use sqlite_loadable::{
     api,
+    scalar::scalar_function_raw,
     table::{IndexInfo, VTab, VTabArguments, VTabCursor},
     BestIndexError, Result,
 };
 
-use std::{mem, os::raw::c_int};
+use std::{mem, os::raw::c_int, os::raw::c_void};
 
-static CREATE_SQL: &str = "CREATE TABLE x(contents, record hidden)";
+use crate::RowPointer;
+
+static CREATE_SQL: &str = "CREATE TABLE x(contents, header, idx, record hidden)";
 enum Columns {
     Contents,
+    Header,
+    Idx,
     Record,
 }
 fn column(index: i32) -> Option<Columns> {
     match index {
         0 => Some(Columns::Contents),
-        1 => Some(Columns::Record),
+        1 => Some(Columns::Header),
+        2 => Some(Columns::Idx),
+        3 => Some(Columns::Record),
         _ => None,
     }
 }
 
+/// The input of xsv_fields, re-supplied in the "record" column.
+enum Input {
+    /// The "row" column of xsv_rows
+    Row(RowPointer),
+    /// The "headers" column of xsv_rows
+    Headers(csv::StringRecord),
+}
+
 #[repr(C)]
 pub struct XsvFieldsTable {
     /// must be first
@@ -81,25 +99,49 @@ impl<'vtab> VTab<'vtab> for XsvFieldsTable {
     }
 }
 
+impl<'vtab> VTabFind<'vtab> for XsvFieldsTable {
+    fn find_function(
+        &mut self,
+        argc: i32,
+        name: &str,
+    ) -> Option<(
+        unsafe extern "C" fn(*mut sqlite3_context, i32, *mut *mut sqlite3_value),
+        Option<i32>,
+        Option<*mut c_void>,
+    )> {
+        if name == "->>" && argc == 2 {
+            return Some((scalar_function_raw(crate::xsv_at), None, None));
+        }
+        None
+    }
+}
+
 #[repr(C)]
 pub struct XsvFieldsCursor {
     /// Base class. Must be first
     base: sqlite3_vtab_cursor,
-    record: csv::StringRecord,
+    input: Option<Input>,
     rowid: i64,
 }
 impl XsvFieldsCursor {
     fn new() -> Result<XsvFieldsCursor> {
         let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
-        let record = csv::StringRecord::new();
 
         let cursor = XsvFieldsCursor {
             base,
             rowid: 0,
-            record,
+            input: None,
         };
         Ok(cursor)
     }
+
+    fn record(&self) -> Option<&csv::StringRecord> {
+        match &self.input {
+            Some(Input::Row(row)) => Some(&row.row),
+            Some(Input::Headers(headers)) => Some(headers),
+            None => None,
+        }
+    }
 }
 
 impl VTabCursor for XsvFieldsCursor {
@@ -109,19 +151,17 @@ impl VTabCursor for XsvFieldsCursor {
         _idx_str: Option<&str>,
         values: &[*mut sqlite3_value],
     ) -> Result<()> {
-        if let Some(record) =
-            unsafe { api::value_pointer::<crate::RowPointer>(&values[0], crate::ROW_POINTER_NAME) }
+        self.input = if let Some(record) =
+            unsafe { api::value_pointer::<RowPointer>(&values[0], crate::ROW_POINTER_NAME) }
         {
-            unsafe {
-                self.record = (*record).row.clone();
-            }
+            Some(Input::Row(unsafe { (*record).clone() }))
         } else if let Some(record) = unsafe {
             api::value_pointer::<crate::StringRecord>(&values[0], crate::HEADERS_POINTER_NAME)
         } {
-            unsafe {
-                self.record = (*record).clone();
-            }
-        }
+            Some(Input::Headers(unsafe { (*record).clone() }))
+        } else {
+            None
+        };
         self.rowid = 0;
         Ok(())
     }
@@ -132,22 +172,48 @@ impl VTabCursor for XsvFieldsCursor {
     }
 
     fn eof(&self) -> bool {
-        self.record.get(self.rowid as usize).is_none()
+        self.record()
+            .and_then(|record| record.get(self.rowid as usize))
+            .is_none()
     }
 
     fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
         match column(i) {
             Some(Columns::Contents) => {
-                if let Some(field) = self.record.get(self.rowid as usize) {
+                if let Some(field) = self
+                    .record()
+                    .and_then(|record| record.get(self.rowid as usize))
+                {
                     api::result_text(context, field)?
                 } else {
                     api::result_null(context)
                 }
             }
-            Some(Columns::Record) => {
-                // TODO re-supply the input record here?
-                api::result_null(context);
+            Some(Columns::Header) => {
+                let header = match &self.input {
+                    Some(Input::Row(row)) => row
+                        .headers
+                        .as_ref()
+                        .and_then(|headers| headers.get(self.rowid as usize)),
+                    _ => None,
+                };
+                match header {
+                    Some(header) => api::result_text(context, header)?,
+                    None => api::result_null(context),
+                }
+            }
+            Some(Columns::Idx) => {
+                api::result_int64(context, self.rowid);
             }
+            Some(Columns::Record) => match &self.input {
+                Some(Input::Row(row)) => {
+                    api::result_pointer(context, crate::ROW_POINTER_NAME, row.clone());
+                }
+                Some(Input::Headers(headers)) => {
+                    api::result_pointer(context, crate::HEADERS_POINTER_NAME, headers.clone());
+                }
+                None => api::result_null(context),
+            },
             None => (),
         }
 
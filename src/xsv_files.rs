@@ -0,0 +1,369 @@
+/**
+ * A SQLite table function that lists the files a glob pattern matches, in the
+ * same order an `xsv` virtual table would read them, with their size,
+ * modification time and compression.
+ *
+ * The compression, header and delimiter of each file are only read when those
+ * columns are selected, and are NULL for files that can't be read.
+ *
+ * ```sql
+ * select path, size, compression, header
+ * from xsv_files('sales-*.csv*', '{"glob_order": "mtime"}');
+ * ```
+ */
+use sqlite_loadable::api::ValueType;
+use sqlite_loadable::prelude::*;
+use sqlite_loadable::vtab_argparse::ConfigOptionValue;
+use sqlite_loadable::{
+    api,
+    table::{IndexInfo, VTab, VTabArguments, VTabCursor},
+    BestIndexError, Error, Result,
+};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::{mem, os::raw::c_int};
+
+use crate::files::{matching_files, parse_glob_order_config_value, GlobOptions};
+use crate::util::{
+    detect_compression, get_csv_source_reader, is_s3_url, is_stream_path, is_url, json_bool_option,
+};
+
+static CREATE_SQL: &str =
+    "CREATE TABLE x(path, size, mtime, compression, delimiter, header, pattern hidden, options hidden)";
+enum Columns {
+    Path,
+    Size,
+    Mtime,
+    Compression,
+    Delimiter,
+    Header,
+    Pattern,
+    Options,
+}
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::Path),
+        1 => Some(Columns::Size),
+        2 => Some(Columns::Mtime),
+        3 => Some(Columns::Compression),
+        4 => Some(Columns::Delimiter),
+        5 => Some(Columns::Header),
+        6 => Some(Columns::Pattern),
+        7 => Some(Columns::Options),
+        _ => None,
+    }
+}
+
+/// Delimiters that are sniffed from the first line of a file, ties going to
+/// the earliest.
+const DELIMITER_CANDIDATES: &[u8] = &[b',', b'\t', b'|', b';'];
+/// Most (uncompressed) bytes read when looking for the end of the first line.
+const SNIFF_LIMIT: u64 = 1024 * 1024;
+
+/// The same glob options as the `xsv` virtual tables, as a JSON object like
+/// '{"glob_order": "mtime", "skip_unreadable": true}'.
+fn parse_options(options: &str) -> Result<GlobOptions> {
+    let options: serde_json::Value = serde_json::from_str(options)
+        .map_err(|e| Error::new_message(format!("xsv_files options must be valid JSON: {e}")))?;
+    let options = options.as_object().ok_or_else(|| {
+        Error::new_message(
+            "xsv_files options must be a JSON object, like '{\"glob_order\": \"mtime\"}'",
+        )
+    })?;
+    let mut result = GlobOptions::default();
+    for (key, value) in options {
+        match key.as_str() {
+            "glob_order" => {
+                let value = value.as_str().ok_or_else(|| {
+                    Error::new_message("'glob_order' option must be 'name', 'mtime', or 'size'")
+                })?;
+                result.order =
+                    parse_glob_order_config_value(ConfigOptionValue::Quoted(value.to_owned()))?;
+            }
+            "glob_case_insensitive" => result.case_insensitive = json_bool_option(key, value)?,
+            "glob_hidden" => result.hidden = json_bool_option(key, value)?,
+            "follow_symlinks" => result.follow_symlinks = json_bool_option(key, value)?,
+            "skip_unreadable" => result.skip_unreadable = json_bool_option(key, value)?,
+            _ => {
+                return Err(Error::new_message(format!(
+                    "Unknown xsv_files option '{key}'"
+                )))
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// A file matched by the pattern. size and mtime are None when its metadata
+/// can't be read, like for a dangling symlink.
+struct FileEntry {
+    path: PathBuf,
+    size: Option<i64>,
+    /// seconds since the unix epoch
+    mtime: Option<i64>,
+}
+
+impl FileEntry {
+    fn new(path: PathBuf) -> FileEntry {
+        let metadata = std::fs::metadata(&path).ok();
+        FileEntry {
+            size: metadata
+                .as_ref()
+                .and_then(|metadata| i64::try_from(metadata.len()).ok()),
+            mtime: metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                .and_then(|mtime| i64::try_from(mtime.as_secs()).ok()),
+            path,
+        }
+    }
+}
+
+/// The compression of the file at `path`, None when it isn't compressed or
+/// can't be read.
+fn read_compression(path: &Path) -> Option<&'static str> {
+    let mut head = vec![];
+    File::open(path)
+        .and_then(|file| file.take(4).read_to_end(&mut head))
+        .ok()?;
+    detect_compression(path, &head)
+}
+
+/// The delimiter and header row sniffed from the first line of a file, both
+/// None when it can't be read.
+#[derive(Default)]
+struct Sniffed {
+    /// None when no candidate delimiter appears in the first line
+    delimiter: Option<u8>,
+    /// None for empty files
+    header: Option<Vec<String>>,
+}
+
+/// The candidate delimiter that appears most often outside of quotes in `line`.
+fn sniff_delimiter(line: &[u8]) -> Option<u8> {
+    let mut counts = [0usize; 4];
+    let mut quoted = false;
+    for byte in line {
+        if *byte == b'"' {
+            quoted = !quoted;
+        } else if !quoted {
+            if let Some(idx) = DELIMITER_CANDIDATES.iter().position(|c| c == byte) {
+                counts[idx] += 1;
+            }
+        }
+    }
+    let mut best = None;
+    let mut best_count = 0;
+    for (delimiter, count) in DELIMITER_CANDIDATES.iter().zip(counts) {
+        if count > best_count {
+            best = Some(*delimiter);
+            best_count = count;
+        }
+    }
+    best
+}
+
+fn sniff(path: &Path) -> Sniffed {
+    let mut line = vec![];
+    let read = get_csv_source_reader(path).and_then(|reader| {
+        BufReader::new(reader.take(SNIFF_LIMIT))
+            .read_until(b'\n', &mut line)
+            .map_err(|e| Error::new_message(format!("Error reading {}: {e}", path.display())))
+    });
+    if read.is_err() {
+        return Sniffed::default();
+    }
+    let delimiter = sniff_delimiter(&line);
+    let header = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter.unwrap_or(b','))
+        .from_reader(line.as_slice())
+        .byte_records()
+        .next()
+        .and_then(|record| record.ok())
+        .map(|record| {
+            record
+                .iter()
+                .map(|field| String::from_utf8_lossy(field).into_owned())
+                .collect()
+        });
+    Sniffed { delimiter, header }
+}
+
+#[repr(C)]
+pub struct XsvFilesTable {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for XsvFilesTable {
+    type Aux = ();
+    type Cursor = XsvFilesCursor;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, XsvFilesTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        Ok((CREATE_SQL.to_string(), XsvFilesTable { base }))
+    }
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        let mut has_pattern = false;
+        for mut constraint in info.constraints() {
+            let argv_index = match column(constraint.column_idx()) {
+                Some(Columns::Pattern) => {
+                    has_pattern = true;
+                    1
+                }
+                Some(Columns::Options) => 2,
+                _ => continue,
+            };
+            if !constraint.usable()
+                || constraint.op() != Some(sqlite_loadable::table::ConstraintOperator::EQ)
+            {
+                return Err(BestIndexError::Constraint);
+            }
+            constraint.set_omit(true);
+            constraint.set_argv_index(argv_index);
+        }
+        if !has_pattern {
+            return Err(BestIndexError::Error);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100);
+        info.set_idxnum(1);
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<XsvFilesCursor> {
+        Ok(XsvFilesCursor::new())
+    }
+}
+
+#[repr(C)]
+pub struct XsvFilesCursor {
+    /// Base class. Must be first
+    base: sqlite3_vtab_cursor,
+    files: Vec<FileEntry>,
+    /// compression of the current file, once it is selected
+    compression: RefCell<Option<Option<&'static str>>>,
+    /// header and delimiter of the current file, once one of them is selected
+    sniffed: RefCell<Option<Sniffed>>,
+    rowid: i64,
+}
+impl XsvFilesCursor {
+    fn new() -> XsvFilesCursor {
+        let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
+        XsvFilesCursor {
+            base,
+            files: vec![],
+            compression: RefCell::new(None),
+            sniffed: RefCell::new(None),
+            rowid: 0,
+        }
+    }
+
+    fn compression_column(&self, context: *mut sqlite3_context) -> Result<()> {
+        let compression = *self
+            .compression
+            .borrow_mut()
+            .get_or_insert_with(|| read_compression(&self.files[self.rowid as usize].path));
+        match compression {
+            Some(compression) => api::result_text(context, compression)?,
+            None => api::result_null(context),
+        }
+        Ok(())
+    }
+
+    fn sniff_column(&self, context: *mut sqlite3_context, columns: Columns) -> Result<()> {
+        let mut sniffed = self.sniffed.borrow_mut();
+        let sniffed = sniffed.get_or_insert_with(|| sniff(&self.files[self.rowid as usize].path));
+        match (columns, sniffed.delimiter, &sniffed.header) {
+            (Columns::Delimiter, Some(delimiter), _) => {
+                api::result_text(context, (delimiter as char).to_string().as_str())?
+            }
+            (Columns::Header, _, Some(header)) => {
+                api::result_json(context, serde_json::Value::from(header.clone()))?
+            }
+            _ => api::result_null(context),
+        }
+        Ok(())
+    }
+}
+
+impl VTabCursor for XsvFilesCursor {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let pattern = api::value_text(&values[0])?;
+        if is_url(pattern) || is_s3_url(pattern) || is_stream_path(pattern) {
+            return Err(Error::new_message(format!(
+                "xsv_files() only lists local files, not {pattern}"
+            )));
+        }
+        let options = match values.get(1) {
+            Some(value) if !matches!(api::value_type(value), ValueType::Null) => {
+                parse_options(api::value_text(value)?)?
+            }
+            _ => GlobOptions::default(),
+        };
+        self.files = matching_files(pattern, &options)?
+            .into_iter()
+            .filter(|path| !path.is_dir())
+            .map(FileEntry::new)
+            .collect();
+        self.compression = RefCell::new(None);
+        self.sniffed = RefCell::new(None);
+        self.rowid = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.compression = RefCell::new(None);
+        self.sniffed = RefCell::new(None);
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.rowid as usize >= self.files.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let entry = &self.files[self.rowid as usize];
+        match column(i) {
+            Some(Columns::Path) => {
+                api::result_text(context, entry.path.to_string_lossy().to_string().as_str())?
+            }
+            Some(Columns::Size) => match entry.size {
+                Some(size) => api::result_int64(context, size),
+                None => api::result_null(context),
+            },
+            Some(Columns::Mtime) => match entry.mtime {
+                Some(mtime) => api::result_int64(context, mtime),
+                None => api::result_null(context),
+            },
+            Some(Columns::Compression) => self.compression_column(context)?,
+            Some(columns @ (Columns::Delimiter | Columns::Header)) => {
+                self.sniff_column(context, columns)?
+            }
+            _ => api::result_null(context),
+        }
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
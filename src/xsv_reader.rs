@@ -7,11 +7,21 @@ use sqlite_loadable::{
     vtab_argparse::*,
     BestIndexError, Error, Result,
 };
-use std::{io::Read, marker::PhantomData, mem, os::raw::c_int};
+use sqlite_reader::SqliteReader;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::{io::Read, mem, os::raw::c_int};
 
+use crate::columns::{
+    parse_column_selector, resolve_column_specs, ColumnMatcher, ColumnSpec, MatchColumns,
+    MissingColumns,
+};
 use crate::util::{
-    get_csv_source_reader, parse_delimiter_config_value, parse_header_config_value,
-    parse_quote_config_value,
+    columns_from_schema, field_count_error, field_count_from_config, get_counted_csv_source_reader,
+    get_csv_source_reader, get_decompressed_reader, parse_aliases_config_value,
+    parse_bool_config_value, parse_delimiter_config_value, parse_header_config_value,
+    parse_match_columns_config_value, parse_missing_columns_config_value, parse_quote_config_value,
+    parse_schema_config_value, parse_sniff_config_value, result_count, CountingReader, FieldCount,
 };
 
 #[repr(C)]
@@ -21,7 +31,10 @@ pub struct XsvReaderTable {
     header: bool,
     delimiter: u8,
     quote: u8,
+    field_count: FieldCount,
     columns: Vec<ColumnDeclaration>,
+    /// when match_columns='name', maps declared columns to fields of each source
+    column_matcher: Option<ColumnMatcher>,
 }
 
 impl<'vtab> VTab<'vtab> for XsvReaderTable {
@@ -36,19 +49,55 @@ impl<'vtab> VTab<'vtab> for XsvReaderTable {
         Self::connect(db, aux, args)
     }
     fn connect(
-        _db: *mut sqlite3,
+        db: *mut sqlite3,
         aux: Option<&Self::Aux>,
         args: VTabArguments,
     ) -> Result<(String, XsvReaderTable)> {
         let arguments = parse_reader_arguments(args.arguments, aux.map(|a| a.to_owned()))?;
         let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let has_selectors = arguments
+            .columns
+            .iter()
+            .any(|spec| matches!(spec, ColumnSpec::Selector(_)));
+        let columns = match &arguments.schema {
+            Some(schema) => columns_from_schema(db, schema)?,
+            None => match (&arguments.sniff, has_selectors) {
+                (Some(sniff), true) => {
+                    let mut reader = csv::ReaderBuilder::new()
+                        .has_headers(true)
+                        .delimiter(arguments.delimiter)
+                        .quote(arguments.quote)
+                        .from_reader(get_csv_source_reader(sniff)?);
+                    let headers = reader.headers().map_err(|_| {
+                        Error::new_message(format!("Error: invalid UTF8 in headers of {sniff}"))
+                    })?;
+                    resolve_column_specs(arguments.columns, Some(headers), sniff)?
+                }
+                _ => resolve_column_specs(arguments.columns, None, "")?,
+            },
+        };
+        // selectors can skip or reorder the sniffed headers, so their columns
+        // are always matched to fields by name
+        let column_matcher = match (arguments.match_columns, has_selectors) {
+            (MatchColumns::Position, false) => None,
+            _ => {
+                let names: Vec<String> = columns.iter().map(|c| c.name.clone()).collect();
+                Some(ColumnMatcher::new(
+                    &names,
+                    &arguments.aliases,
+                    arguments.missing_columns,
+                ))
+            }
+        };
 
         let vtab = XsvReaderTable {
             base,
             header: arguments.header,
             delimiter: arguments.delimiter,
             quote: arguments.quote,
-            columns: arguments.columns,
+            field_count: arguments.field_count,
+            columns,
+            column_matcher,
         };
 
         let mut sql = String::from("create table x( _source hidden");
@@ -56,7 +105,8 @@ impl<'vtab> VTab<'vtab> for XsvReaderTable {
             sql.push(',');
             sql.push_str(column.vtab_declaration().as_str());
         }
-        sql.push(')');
+        sql.push_str(", _line hidden, _byte hidden, _length hidden, _path hidden");
+        sql.push_str(", _bytes_read hidden, _total_bytes hidden, _uncompressed_bytes_read hidden)");
         Ok((sql, vtab))
     }
     fn destroy(&self) -> Result<()> {
@@ -91,7 +141,7 @@ impl<'vtab> VTab<'vtab> for XsvReaderTable {
     }
 
     fn open(&mut self) -> Result<XsvReaderCursor<'_>> {
-        XsvReaderCursor::new(self.delimiter, self.quote, &self.columns, self.header)
+        XsvReaderCursor::new(self)
     }
 }
 
@@ -99,44 +149,87 @@ impl<'vtab> VTab<'vtab> for XsvReaderTable {
 pub struct XsvReaderCursor<'vtab> {
     /// Base class. Must be first
     base: sqlite3_vtab_cursor,
-    header: bool,
-    delimiter: u8,
-    quote: u8,
-    columns: &'vtab Vec<ColumnDeclaration>,
+    table: &'vtab XsvReaderTable,
+    /// field index for each declared column of the current source, when match_columns='name'
+    column_mapping: Option<Vec<Option<usize>>>,
+    /// Name of the current source in error messages: its path, "BLOB", or "reader object"
+    source: String,
+    /// path of the current source, NULL in _path for BLOBs and reader objects
     path: Option<String>,
+    /// bytes read from the current source so far, before decompression
+    bytes_read: Rc<Cell<u64>>,
+    /// size of the current source before decompression, NULL in _total_bytes for reader objects
+    total_bytes: Option<u64>,
     current_reader: Option<csv::Reader<Box<dyn Read>>>,
     record: csv::StringRecord,
     rowid: i64,
     eof: bool,
-    phantom: PhantomData<&'vtab XsvReaderTable>,
 }
-impl XsvReaderCursor<'_> {
-    fn new(
-        delimiter: u8,
-        quote: u8,
-        columns: &Vec<ColumnDeclaration>,
-        header: bool,
-    ) -> Result<XsvReaderCursor> {
+impl<'vtab> XsvReaderCursor<'vtab> {
+    fn new(table: &'vtab XsvReaderTable) -> Result<XsvReaderCursor<'vtab>> {
         let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
         let record = csv::StringRecord::new();
 
         let cursor = XsvReaderCursor {
             base,
-            header,
-            delimiter,
-            quote,
-            columns,
+            table,
+            column_mapping: None,
+            source: String::new(),
             path: None,
+            bytes_read: Rc::new(Cell::new(0)),
+            total_bytes: None,
             current_reader: None,
             rowid: 0,
             record,
             eof: false,
-            phantom: PhantomData,
         };
         Ok(cursor)
     }
 }
 
+impl XsvReaderCursor<'_> {
+    fn source(&self) -> &str {
+        self.source.as_str()
+    }
+
+    /// The hidden _line, _byte, _length, _path, _bytes_read, _total_bytes and
+    /// _uncompressed_bytes_read columns, which are declared after the table's
+    /// columns. `offset` is the index after the last declared column.
+    fn provenance_column(&self, context: *mut sqlite3_context, offset: usize) -> Result<()> {
+        match offset {
+            0 => match self.record.position() {
+                Some(position) => result_count(context, position.line())?,
+                None => api::result_null(context),
+            },
+            1 => match self.record.position() {
+                Some(position) => result_count(context, position.byte())?,
+                None => api::result_null(context),
+            },
+            2 => result_count(context, self.record.len() as u64)?,
+            3 => match &self.path {
+                Some(path) => api::result_text(context, path.as_str())?,
+                None => api::result_null(context),
+            },
+            4 => result_count(context, self.bytes_read.get())?,
+            5 => match self.total_bytes {
+                Some(total_bytes) => result_count(context, total_bytes)?,
+                None => api::result_null(context),
+            },
+            6 => match &self.current_reader {
+                Some(reader) => result_count(context, reader.position().byte())?,
+                None => api::result_null(context),
+            },
+            _ => {
+                return Err(Error::new_message(format!(
+                    "Internal sqlite-xsv error: invalid column index {}",
+                    offset + self.table.columns.len()
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
 impl VTabCursor for XsvReaderCursor<'_> {
     fn filter(
         &mut self,
@@ -148,19 +241,60 @@ impl VTabCursor for XsvReaderCursor<'_> {
             .get(0)
             .ok_or_else(|| Error::new_message("Internal error: expected argv[0] in xFilter"))?;
 
+        self.bytes_read = Rc::new(Cell::new(0));
         let r = match api::value_type(input_arg) {
-            ValueType::Blob => Box::new(std::io::Cursor::new(api::value_blob(input_arg))),
+            ValueType::Blob => {
+                self.source = String::from("BLOB");
+                self.path = None;
+                let blob = api::value_blob(input_arg);
+                self.total_bytes = Some(blob.len() as u64);
+                get_decompressed_reader(Box::new(CountingReader::new(
+                    std::io::Cursor::new(blob),
+                    &self.bytes_read,
+                )))?
+            }
+            // pointers from sqlite-reader extensions have a NULL type
+            ValueType::Null => {
+                let reader = unsafe {
+                    api::value_pointer::<Box<dyn SqliteReader>>(
+                        input_arg,
+                        crate::READER_POINTER_NAME,
+                    )
+                }
+                .ok_or_else(|| {
+                    Error::new_message(
+                        "the source of xsv_reader must be a path, BLOB, or reader object, not NULL",
+                    )
+                })?;
+                self.source = String::from("reader object");
+                self.path = None;
+                self.total_bytes = None;
+                let r = unsafe { (*(*reader)).generate() }.map_err(|e| {
+                    Error::new_message(format!("Error reading from reader object: {e}"))
+                })?;
+                get_decompressed_reader(Box::new(CountingReader::new(r, &self.bytes_read)))?
+            }
             _ => {
                 let path = api::value_text(input_arg)?;
+                self.source = path.to_owned();
                 self.path = Some(path.to_owned());
-                get_csv_source_reader(path)?
+                self.total_bytes = std::fs::metadata(path).ok().map(|m| m.len());
+                get_counted_csv_source_reader(path, &self.bytes_read)?
             }
         };
-        let reader = csv::ReaderBuilder::new()
-            .has_headers(self.header)
-            .delimiter(self.delimiter)
-            .quote(self.quote)
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(self.table.header)
+            .delimiter(self.table.delimiter)
+            .quote(self.table.quote)
+            .flexible(self.table.field_count == FieldCount::Flexible)
             .from_reader(r);
+        if let Some(matcher) = &self.table.column_matcher {
+            let source = self.source();
+            let headers = reader.headers().map_err(|_| {
+                Error::new_message(format!("Error: invalid UTF8 in headers of {source}"))
+            })?;
+            self.column_mapping = Some(matcher.mapping(headers, source)?);
+        }
         self.current_reader = Some(reader);
         self.next()
     }
@@ -181,13 +315,18 @@ impl VTabCursor for XsvReaderCursor<'_> {
                 csv::ErrorKind::Utf8 { pos: _, err: _ } => Err(Error::new_message(
                     "Error: UTF8 error while reading next row",
                 )),
+                csv::ErrorKind::UnequalLengths {
+                    pos,
+                    expected_len,
+                    len,
+                } => Err(field_count_error(
+                    self.source(),
+                    pos.as_ref(),
+                    expected_len.to_string().as_str(),
+                    *len,
+                )),
                 _ => Err(Error::new_message(
-                    format!(
-                        "Error reading {}: {}",
-                        self.path.as_ref().map_or("", |p| p),
-                        err
-                    )
-                    .as_str(),
+                    format!("Error reading {}: {}", self.source(), err).as_str(),
                 )),
             },
         }
@@ -201,17 +340,34 @@ impl VTabCursor for XsvReaderCursor<'_> {
         if i < 1 {
             return Ok(());
         }
-        let i = usize::try_from(i - 1)
-            .map_err(|_| Error::new_message(format!("what the fuck {}", i).as_str()))?;
-        let column = self
-            .columns
-            .get(i)
-            .ok_or_else(|| Error::new_message("what the fuck"))?;
-        let s = &self
-            .record
-            .get(i)
-            .ok_or_else(|| Error::new_message(format!("wut {}", i).as_str()))?;
-        column.affinity().result_text(context, s)?;
+        // column 0 is _source, declared columns start at 1
+        let i = (i - 1) as usize;
+        if i >= self.table.columns.len() {
+            return self.provenance_column(context, i - self.table.columns.len());
+        }
+        let column = &self.table.columns[i];
+        let field = match &self.column_mapping {
+            Some(mapping) => match mapping.get(i).copied().flatten() {
+                Some(field) => field,
+                // declared column not present in this source, missing_columns='null'
+                None => return Ok(()),
+            },
+            None => i,
+        };
+        match self.record.get(field) {
+            Some(s) => column.affinity().result_text(context, s)?,
+            // like xsv, declared columns past the end of a record are NULL unless strict=on
+            None => {
+                if self.table.field_count == FieldCount::Strict {
+                    return Err(field_count_error(
+                        self.source(),
+                        self.record.position(),
+                        format!("at least {}", field + 1).as_str(),
+                        self.record.len() as u64,
+                    ));
+                }
+            }
+        }
         Ok(())
     }
 
@@ -221,10 +377,17 @@ impl VTabCursor for XsvReaderCursor<'_> {
 }
 
 struct ReaderArguments {
-    columns: Vec<ColumnDeclaration>,
+    columns: Vec<ColumnSpec>,
     header: bool,
     delimiter: u8,
     quote: u8,
+    schema: Option<String>,
+    match_columns: MatchColumns,
+    missing_columns: MissingColumns,
+    aliases: Vec<(String, Vec<String>)>,
+    field_count: FieldCount,
+    /// sample file whose headers column selectors like `*` are resolved against
+    sniff: Option<String>,
 }
 
 fn parse_reader_arguments(
@@ -235,11 +398,22 @@ fn parse_reader_arguments(
     let mut delimiter = initial_delimiter;
     let mut quote = b'"';
     let mut header = true;
+    let mut schema = None;
+    let mut match_columns = MatchColumns::Position;
+    let mut missing_columns = MissingColumns::Error;
+    let mut aliases = vec![];
+    let mut strict = None;
+    let mut flexible = None;
+    let mut sniff = None;
     for arg in arguments {
+        if let Some(selector) = parse_column_selector(arg.as_str())? {
+            columns.push(ColumnSpec::Selector(selector));
+            continue;
+        }
         match parse_argument(arg.as_str()) {
             Ok(arg) => match arg {
                 Argument::Column(column_definition) => {
-                    columns.push(column_definition);
+                    columns.push(ColumnSpec::Declared(column_definition));
                 }
                 Argument::Config(config) => match config.key.as_str() {
                     "delimiter" => {
@@ -251,12 +425,53 @@ fn parse_reader_arguments(
                     "header" => {
                         header = parse_header_config_value(config.value)?;
                     }
+                    "schema" => {
+                        schema = Some(parse_schema_config_value(config.value)?);
+                    }
+                    "match_columns" => {
+                        match_columns = parse_match_columns_config_value(config.value)?;
+                    }
+                    "missing_columns" => {
+                        missing_columns = parse_missing_columns_config_value(config.value)?;
+                    }
+                    "aliases" => {
+                        aliases = parse_aliases_config_value(config.value)?;
+                    }
+                    "strict" => {
+                        strict = Some(parse_bool_config_value("strict", config.value)?);
+                    }
+                    "flexible" => {
+                        flexible = Some(parse_bool_config_value("flexible", config.value)?);
+                    }
+                    "sniff" => {
+                        sniff = Some(parse_sniff_config_value(config.value)?);
+                    }
                     _ => (),
                 },
             },
             Err(err) => return Err(Error::new_message(err.as_str())),
         };
     }
+    if schema.is_some() && !columns.is_empty() {
+        return Err(Error::new_message(
+            "'schema' cannot be used alongside column declarations",
+        ));
+    }
+    let field_count = field_count_from_config(strict, flexible)?;
+    if match_columns == MatchColumns::Name && !header {
+        return Err(Error::new_message(
+            "match_columns='name' requires a header row, but header is off",
+        ));
+    }
+    if !header
+        && columns
+            .iter()
+            .any(|spec| matches!(spec, ColumnSpec::Selector(_)))
+    {
+        return Err(Error::new_message(
+            "column selectors like '*' require a header row, but header is off",
+        ));
+    }
     let delimiter = delimiter.ok_or_else(|| {
         Error::new_message("no delimiter given. Specify a delimiter to use with 'delimiter=\"\t\"'")
     })?;
@@ -266,5 +481,11 @@ fn parse_reader_arguments(
         header,
         delimiter,
         quote,
+        schema,
+        match_columns,
+        missing_columns,
+        aliases,
+        field_count,
+        sniff,
     })
 }
@@ -3,7 +3,11 @@
  *
  * The "source" can be one of:
  *  1. A raw CSV in a BLOB
- *  2. A SQLite "reader" object
+ *  2. A raw CSV in TEXT
+ *  3. A SQLite "reader" object
+ *
+ * The `*_rows_file` variants instead take a path to a CSV file as the source,
+ * which is decompressed the same way as the `xsv` virtual table.
  *
  *  Overloads the "->>" operator as an alias for `xsv_at()`.
  */
@@ -20,8 +24,14 @@ use sqlite_reader::SqliteReader;
 use std::os::raw::c_void;
 use std::{io::Read, mem, os::raw::c_int};
 
-static CREATE_SQL: &str =
-    "CREATE TABLE x(row, headers, line, byte, length, source hidden, delimiter hidden)";
+use crate::util::{
+    get_csv_source_reader, get_decompressed_reader, json_bool_option, json_character_option,
+};
+
+static CREATE_SQL: &str = "CREATE TABLE x(row, headers, line, byte, length, source hidden, delimiter hidden, options hidden, row_json hidden, row_array hidden)";
+// csv_rows/tsv_rows have a fixed delimiter, so options is the 2nd argument
+static CREATE_SQL_FIXED_DELIMITER: &str =
+    "CREATE TABLE x(row, headers, line, byte, length, source hidden, options hidden, row_json hidden, row_array hidden)";
 enum Columns {
     Row,
     Headers,
@@ -30,44 +40,138 @@ enum Columns {
     Length,
     Source,
     Delimiter,
+    Options,
+    RowJson,
+    RowArray,
 }
-fn column(index: i32) -> Option<Columns> {
-    match index {
-        0 => Some(Columns::Row),
-        1 => Some(Columns::Headers),
-        2 => Some(Columns::Line),
-        3 => Some(Columns::Byte),
-        4 => Some(Columns::Length),
-        5 => Some(Columns::Source),
-        6 => Some(Columns::Delimiter),
+fn column(index: i32, fixed_delimiter: bool) -> Option<Columns> {
+    match (index, fixed_delimiter) {
+        (0, _) => Some(Columns::Row),
+        (1, _) => Some(Columns::Headers),
+        (2, _) => Some(Columns::Line),
+        (3, _) => Some(Columns::Byte),
+        (4, _) => Some(Columns::Length),
+        (5, _) => Some(Columns::Source),
+        (6, false) => Some(Columns::Delimiter),
+        (6, true) | (7, false) => Some(Columns::Options),
+        (7, true) | (8, false) => Some(Columns::RowJson),
+        (8, true) | (9, false) => Some(Columns::RowArray),
         _ => None,
     }
 }
 
+/// Dialect options for xsv_rows, given as a JSON object in the "options" argument,
+/// like `csv_rows(source, '{"header": "off", "quote": "'"}')`.
+struct RowsOptions {
+    header: bool,
+    quote: u8,
+    escape: Option<u8>,
+    comment: Option<u8>,
+    trim: csv::Trim,
+}
+
+impl Default for RowsOptions {
+    fn default() -> Self {
+        RowsOptions {
+            header: true,
+            quote: b'"',
+            escape: None,
+            comment: None,
+            trim: csv::Trim::None,
+        }
+    }
+}
+
+impl RowsOptions {
+    fn parse(options: &str) -> Result<RowsOptions> {
+        let options: serde_json::Value = serde_json::from_str(options)
+            .map_err(|e| Error::new_message(format!("xsv_rows options must be valid JSON: {e}")))?;
+        let options = options.as_object().ok_or_else(|| {
+            Error::new_message("xsv_rows options must be a JSON object, like '{\"header\": false}'")
+        })?;
+        let mut result = RowsOptions::default();
+        for (key, value) in options {
+            match key.as_str() {
+                "header" => {
+                    result.header = json_bool_option(key, value)?
+                }
+                "quote" => result.quote = json_character_option(key, value)?,
+                "escape" => result.escape = Some(json_character_option(key, value)?),
+                "comment" => result.comment = Some(json_character_option(key, value)?),
+                "trim" => {
+                    result.trim = match value {
+                        serde_json::Value::Bool(true) => csv::Trim::All,
+                        serde_json::Value::Bool(false) => csv::Trim::None,
+                        serde_json::Value::String(s) => match s.to_lowercase().as_str() {
+                            "all" => csv::Trim::All,
+                            "headers" => csv::Trim::Headers,
+                            "fields" => csv::Trim::Fields,
+                            "none" => csv::Trim::None,
+                            _ => {
+                                return Err(Error::new_message(format!(
+                                    "Unknown trim value '{s}', expected 'all', 'headers', 'fields', or 'none'"
+                                )))
+                            }
+                        },
+                        _ => return Err(Error::new_message("Unknown trim value")),
+                    }
+                }
+                _ => {
+                    return Err(Error::new_message(format!(
+                        "Unknown xsv_rows option '{key}'"
+                    )))
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// What the "source" argument of a xsv_rows table function is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowsInput {
+    /// CSV content as a BLOB, TEXT, or a reader object
+    Value,
+    /// A path to a CSV file
+    File,
+}
+
+pub struct XsvRowsAux {
+    pub delimiter: Option<u8>,
+    pub input: RowsInput,
+}
+
 #[repr(C)]
 pub struct XsvRowsTable {
     /// must be first
     base: sqlite3_vtab,
     delimiter: Option<u8>,
+    input: RowsInput,
 }
 
 impl<'vtab> VTab<'vtab> for XsvRowsTable {
-    type Aux = u8;
+    type Aux = XsvRowsAux;
     type Cursor = XsvRowsCursor;
 
     fn connect(
         _db: *mut sqlite3,
         aux: Option<&Self::Aux>,
-        args: VTabArguments,
+        _args: VTabArguments,
     ) -> Result<(String, XsvRowsTable)> {
         let base: sqlite3_vtab = unsafe { mem::zeroed() };
 
         let vtab = XsvRowsTable {
             base,
-            delimiter: aux.copied(),
+            delimiter: aux.and_then(|aux| aux.delimiter),
+            input: aux.map_or(RowsInput::Value, |aux| aux.input),
+        };
+        let sql = if vtab.delimiter.is_some() {
+            CREATE_SQL_FIXED_DELIMITER
+        } else {
+            CREATE_SQL
         };
 
-        Ok((CREATE_SQL.to_string(), vtab))
+        Ok((sql.to_string(), vtab))
     }
     fn destroy(&self) -> Result<()> {
         Ok(())
@@ -76,9 +180,10 @@ impl<'vtab> VTab<'vtab> for XsvRowsTable {
     fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
         let mut has_source = false;
         let mut has_delimiter = false;
+        let mut has_options = false;
         let requires_delimiter = self.delimiter.is_none();
         for mut constraint in info.constraints() {
-            match column(constraint.column_idx()) {
+            match column(constraint.column_idx(), !requires_delimiter) {
                 Some(Columns::Source) => {
                     if !has_source && !constraint.usable()
                         || constraint.op() != Some(sqlite_loadable::table::ConstraintOperator::EQ)
@@ -101,10 +206,18 @@ impl<'vtab> VTab<'vtab> for XsvRowsTable {
                         return Err(BestIndexError::Constraint);
                     }
                 }
+                Some(Columns::Options) => {
+                    if !constraint.usable() {
+                        return Err(BestIndexError::Constraint);
+                    }
+                    has_options = true;
+                    constraint.set_omit(true);
+                    constraint.set_argv_index(if requires_delimiter { 3 } else { 2 });
+                }
                 _ => (),
             }
         }
-        if !has_source {
+        if !has_source || (requires_delimiter && has_options && !has_delimiter) {
             return Err(BestIndexError::Error);
         }
         info.set_estimated_cost(100000.0);
@@ -114,7 +227,7 @@ impl<'vtab> VTab<'vtab> for XsvRowsTable {
     }
 
     fn open(&mut self) -> Result<XsvRowsCursor> {
-        XsvRowsCursor::new(self.delimiter)
+        XsvRowsCursor::new(self.delimiter, self.input)
     }
 }
 
@@ -140,6 +253,9 @@ pub struct XsvRowsCursor {
     /// Base class. Must be first
     base: sqlite3_vtab_cursor,
     delimiter: Option<u8>,
+    input: RowsInput,
+    /// delimiter of the current source, either the fixed one or from argv
+    record_delimiter: u8,
     current_reader: Option<csv::Reader<Box<dyn Read>>>,
     headers: csv::StringRecord,
     record: csv::StringRecord,
@@ -147,13 +263,15 @@ pub struct XsvRowsCursor {
     eof: bool,
 }
 impl XsvRowsCursor {
-    fn new(delimiter: Option<u8>) -> Result<XsvRowsCursor> {
+    fn new(delimiter: Option<u8>, input: RowsInput) -> Result<XsvRowsCursor> {
         let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
         let record = csv::StringRecord::new();
 
         let cursor = XsvRowsCursor {
             base,
             delimiter,
+            input,
+            record_delimiter: b',',
             current_reader: None,
             rowid: 0,
             headers: csv::StringRecord::new(),
@@ -175,31 +293,73 @@ impl VTabCursor for XsvRowsCursor {
             .get(0)
             .ok_or_else(|| Error::new_message("Internal error: expected argv[0] in xFilter"))?;
         // either self.delimiter or argv[1] must define the delimiter
-        let delimiter = values
-            .get(1)
-            .map_or(self.delimiter, |v| {
-                api::value_text(v).unwrap().as_bytes().first().copied()
-            })
-            .unwrap();
-        let reader = match api::value_type(input_arg) {
-            ValueType::Blob => Box::new(std::io::Cursor::new(api::value_blob(input_arg))),
-            ValueType::Null => match unsafe {
-                api::value_pointer::<Box<dyn SqliteReader>>(input_arg, b"reader0\0")
+        let (delimiter_arg, options_arg) = match self.delimiter {
+            Some(_) => (None, values.get(1)),
+            None => (values.get(1), values.get(2)),
+        };
+        let options = match options_arg {
+            Some(value) if !matches!(api::value_type(value), ValueType::Null) => {
+                RowsOptions::parse(api::value_text(value)?)?
+            }
+            _ => RowsOptions::default(),
+        };
+        let delimiter = match delimiter_arg {
+            Some(value) => api::value_text(value)?.as_bytes().first().copied(),
+            None => self.delimiter,
+        }
+        .ok_or_else(|| Error::new_message("a delimiter is required, like xsv_rows(source, '|')"))?;
+        self.record_delimiter = delimiter;
+        let reader: Box<dyn Read> = match (self.input, api::value_type(input_arg)) {
+            (RowsInput::File, ValueType::Text) => {
+                get_csv_source_reader(api::value_text(input_arg)?)?
+            }
+            (RowsInput::File, _) => {
+                return Err(Error::new_message(
+                    "the source of a *_rows_file function must be a TEXT path",
+                ))
+            }
+            (RowsInput::Value, ValueType::Blob) => {
+                get_decompressed_reader(Box::new(std::io::Cursor::new(api::value_blob(input_arg))))?
+            }
+            (RowsInput::Value, ValueType::Text) => Box::new(std::io::Cursor::new(
+                api::value_text(input_arg)?.as_bytes().to_vec(),
+            )),
+            (RowsInput::Value, ValueType::Null) => match unsafe {
+                api::value_pointer::<Box<dyn SqliteReader>>(input_arg, crate::READER_POINTER_NAME)
             } {
-                Some(reader) => unsafe {
-                    let r = (*(*reader)).generate().unwrap();
-                    r
-                },
-                None => todo!(),
+                Some(reader) => {
+                    let r = unsafe { (*(*reader)).generate() }.map_err(|e| {
+                        Error::new_message(format!("Error reading from reader object: {e}"))
+                    })?;
+                    get_decompressed_reader(r)?
+                }
+                None => return Err(Error::new_message("the source of xsv_rows is NULL")),
             },
-            _ => todo!("unknown value type?"),
+            (RowsInput::Value, _) => {
+                return Err(Error::new_message(
+                    "the source of xsv_rows must be a BLOB, TEXT, or reader object",
+                ))
+            }
         };
         let mut reader = csv::ReaderBuilder::new()
-            .has_headers(true)
+            .has_headers(options.header)
             .delimiter(delimiter)
+            .quote(options.quote)
+            .escape(options.escape)
+            .double_quote(options.escape.is_none())
+            .comment(options.comment)
+            .trim(options.trim)
             .flexible(true)
             .from_reader(reader);
-        self.headers = reader.headers().unwrap().clone();
+        let headers = reader
+            .headers()
+            .map_err(|_| Error::new_message("Error: invalid UTF8 in headers of CSV"))?;
+        self.headers = if options.header {
+            headers.clone()
+        } else {
+            // without a header row, fields are named by position: c1, c2, ...
+            (1..=headers.len()).map(|i| format!("c{i}")).collect()
+        };
         self.current_reader = Some(reader);
         self.next()
     }
@@ -232,7 +392,7 @@ impl VTabCursor for XsvRowsCursor {
     }
 
     fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
-        match column(i) {
+        match column(i, self.delimiter.is_some()) {
             Some(Columns::Row) => {
                 api::result_pointer(
                     context,
@@ -240,6 +400,7 @@ impl VTabCursor for XsvRowsCursor {
                     crate::RowPointer {
                         row: self.record.clone(),
                         headers: Some(self.headers.clone()),
+                        delimiter: self.record_delimiter,
                     },
                 );
             }
@@ -258,10 +419,21 @@ impl VTabCursor for XsvRowsCursor {
             Some(Columns::Length) => {
                 api::result_int64(context, self.record.len().try_into().unwrap())
             }
+            Some(Columns::RowJson) => {
+                let row = crate::RowPointer {
+                    row: self.record.clone(),
+                    headers: Some(self.headers.clone()),
+                    delimiter: self.record_delimiter,
+                };
+                api::result_json(context, row.to_json_object())?;
+            }
+            Some(Columns::RowArray) => {
+                api::result_json(context, crate::record_to_json_array(&self.record))?;
+            }
             Some(Columns::Source) => {
                 api::result_null(context);
             }
-            Some(Columns::Delimiter) => {
+            Some(Columns::Delimiter) | Some(Columns::Options) => {
                 api::result_null(context);
             }
             None => (),
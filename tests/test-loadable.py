@@ -1,10 +1,16 @@
+import gzip
+import http.server
 import sqlite3
 import unittest
+import urllib.parse
 import time
 import os
 import sys
 
 import platform
+import subprocess
+import tempfile
+import threading
 
 IS_WINDOWS = platform.system() == "Windows"
 
@@ -47,31 +53,51 @@ def execute_all(sql, args=None):
 
 FUNCTIONS = [
     "csv_at",
+    "csv_export",
+    "csv_import",
     "csv_line_number",
     "csv_path",
+    "csv_slice",
     "tsv_at",
+    "tsv_export",
+    "tsv_import",
     "tsv_line_number",
     "tsv_path",
+    "tsv_slice",
     "xsv_at",
+    "xsv_date",
     "xsv_debug",
+    "xsv_export",
+    "xsv_import",
+    "xsv_int",
     "xsv_line_number",
     "xsv_path",
+    "xsv_real",
+    "xsv_slice",
+    "xsv_to_json",
     "xsv_version",
 ]
 
 MODULES = [
     "csv",
+    "csv_export_partitioned",
     "csv_fields",
     "csv_reader",
     "csv_rows",
+    "csv_rows_file",
     "tsv",
+    "tsv_export_partitioned",
     "tsv_fields",
     "tsv_reader",
     "tsv_rows",
+    "tsv_rows_file",
     "xsv",
+    "xsv_export_partitioned",
     "xsv_fields",
+    "xsv_files",
     "xsv_reader",
     "xsv_rows",
+    "xsv_rows_file",
 ]
 
 
@@ -98,37 +124,920 @@ class TestXsv(unittest.TestCase):
 
     def test_xsv_debug(self):
         debug = db.execute("select xsv_debug()").fetchone()[0]
-        self.assertEqual(len(debug.splitlines()), 2)
+        self.assertEqual(len(debug.splitlines()), 3)
+        self.assertRegex(debug, "Features: .*gzip_support")
 
     def test_xsv_at(self):
-        self.skipTest("TODO")
+        xsv_at = lambda *args: db.execute(
+            "select xsv_at(row, "
+            + ", ".join("?" * len(args))
+            + ") from xsv_rows('id|Name|age\n1|alex|10', '|')",
+            args,
+        ).fetchone()[0]
+        self.assertEqual(xsv_at(1), "alex")
+        self.assertEqual(xsv_at(-1), "10")
+        self.assertEqual(xsv_at(-3), "1")
+        self.assertEqual(xsv_at(-4), None)
+        self.assertEqual(xsv_at(3), None)
+        self.assertEqual(xsv_at("Name"), "alex")
+        self.assertEqual(xsv_at("name"), None)
+        self.assertEqual(xsv_at("name", 1), "alex")
+        self.assertEqual(xsv_at("NAME", True), "alex")
+        self.assertEqual(xsv_at("NAME", None), None)
+        for value, name in [("'true'", "TEXT"), ("1.0", "REAL")]:
+            self.exec_fails_with(
+                f"select xsv_at(row, 'A', {value}) from csv_rows('a,b\n1,2')",
+                f"xsv_at\\(\\) case_insensitive must be an INTEGER, like 1 or true, not {name}",
+            )
+
+        for value, name in [("1.5", "REAL"), ("x'01'", "BLOB"), ("null", "NULL")]:
+            self.exec_fails_with(
+                f"select xsv_at(row, {value}) from csv_rows('a,b\n1,2')",
+                f"xsv_at\\(\\) selector must be an INTEGER index or TEXT header name, not {name}",
+            )
 
     def test_csv_at(self):
-        self.skipTest("TODO")
+        self.assertEqual(
+            execute_all(
+                "select csv_at(row, -1) as last, row ->> -2 as second_last, csv_at(headers, -1) as header from csv_rows('a,b,c\n1,2,3')"
+            ),
+            [{"last": "3", "second_last": "2", "header": "c"}],
+        )
+        self.exec_fails_with(
+            "select row ->> 1.0 from csv_rows('a,b\n1,2')",
+            "selector must be an INTEGER index or TEXT header name, not REAL",
+        )
+        self.exec_fails_with(
+            "select csv_at(headers, 'a') from csv_rows('a,b\n1,2')",
+            "xsv_at\\(\\) on headers requires an INTEGER index, not TEXT",
+        )
 
     def test_tsv_at(self):
-        self.skipTest("TODO")
+        self.assertEqual(
+            execute_all(
+                "select tsv_at(row, 'B', 1) as b from tsv_rows('a\tb\n1\t2')"
+            ),
+            [{"b": "2"}],
+        )
+
+    def test_xsv_int(self):
+        xsv_int = lambda field, *options: db.execute(
+            "select xsv_int(row, 'value'"
+            + ", ?" * len(options)
+            + ") from xsv_rows(?, '|')",
+            [*options, "value\n" + field],
+        ).fetchone()[0]
+        self.assertEqual(xsv_int("42"), 42)
+        self.assertEqual(xsv_int(" -7 "), -7)
+        self.assertEqual(xsv_int(""), None)
+        self.assertEqual(xsv_int("1,234"), None)
+        self.assertEqual(xsv_int("1,234", '{"thousands": ","}'), 1234)
+        self.assertEqual(xsv_int("1.234.567", '{"thousands": "."}'), 1234567)
+        self.assertEqual(xsv_int("12.5"), None)
+        self.assertEqual(
+            db.execute(
+                "select xsv_int(row, 'AGE', '{\"case_insensitive\": true}') from csv_rows('age\n10')"
+            ).fetchone()[0],
+            10,
+        )
+        self.exec_fails_with(
+            """select xsv_int(row, 0, '{"on_error": "error"}') from csv_rows('a\nabc')""",
+            "xsv_int\\(\\): 'abc' is not a valid integer",
+        )
+        self.exec_fails_with(
+            """select xsv_int(row, 0, '{"thousands": ",", "decimal": ","}') from csv_rows('a\n1')""",
+            "thousands and decimal separators must be different",
+        )
+        self.exec_fails_with(
+            """select xsv_int(row, 0, '{"thousand": ","}') from csv_rows('a\n1')""",
+            "Unknown xsv_int\\(\\) option 'thousand'",
+        )
+        self.exec_fails_with(
+            "select xsv_int('1', 0)",
+            "xsv_int\\(\\) expects a row value from xsv_rows",
+        )
+
+    def test_xsv_real(self):
+        xsv_real = lambda field, *options: db.execute(
+            "select xsv_real(row, 0"
+            + ", ?" * len(options)
+            + ") from xsv_rows(?, '|')",
+            [*options, "value\n" + field],
+        ).fetchone()[0]
+        self.assertEqual(xsv_real("1.5"), 1.5)
+        self.assertEqual(xsv_real("1,234.50"), None)
+        self.assertEqual(xsv_real("1,234.50", '{"thousands": ","}'), 1234.5)
+        self.assertEqual(
+            xsv_real("1.234,50", '{"thousands": ".", "decimal": ","}'), 1234.5
+        )
+        self.assertEqual(xsv_real("1e3"), 1000.0)
+        self.assertEqual(xsv_real("inf"), None)
+        self.assertEqual(xsv_real("n/a"), None)
+        self.exec_fails_with(
+            """select xsv_real(row, 0, '{"on_error": "error"}') from csv_rows('a\nn/a')""",
+            "xsv_real\\(\\): 'n/a' is not a valid real",
+        )
+
+    def test_xsv_date(self):
+        xsv_date = lambda field, *options: db.execute(
+            "select xsv_date(row, 0"
+            + ", ?" * len(options)
+            + ") from xsv_rows(?, '|')",
+            [*options, "value\n" + field],
+        ).fetchone()[0]
+        self.assertEqual(xsv_date("2022-01-31"), "2022-01-31")
+        self.assertEqual(xsv_date("2022-1-5"), "2022-01-05")
+        self.assertEqual(xsv_date("2022-02-30"), None)
+        self.assertEqual(xsv_date("2024-02-29"), "2024-02-29")
+        self.assertEqual(xsv_date("2023-02-29"), None)
+        self.assertEqual(xsv_date("01/31/2022"), None)
+        self.assertEqual(xsv_date("01/31/2022", '{"format": "%m/%d/%Y"}'), "2022-01-31")
+        self.assertEqual(xsv_date("31.01.2022", '{"format": "%d.%m.%Y"}'), "2022-01-31")
+        self.exec_fails_with(
+            """select xsv_date(row, 0, '{"on_error": "error"}') from csv_rows('a\n2022-13-01')""",
+            "xsv_date\\(\\): '2022-13-01' is not a valid date in the format '%Y-%m-%d'",
+        )
+        # unsupported formats are errors, even with on_error 'null'
+        self.exec_fails_with(
+            """select xsv_date(row, 0, '{"format": "%d/%m/%y"}') from csv_rows('a\n01/02/22')""",
+            "xsv_date\\(\\): unsupported directive '%y' in format",
+        )
+        self.exec_fails_with(
+            """select xsv_date(row, 0, '{"format": "%d %b %Y"}') from csv_rows('a\n01 Feb 2022')""",
+            "unsupported directive '%b' in format",
+        )
+        self.exec_fails_with(
+            """select xsv_date(row, 0, '{"format": "%m/%Y"}') from csv_rows('a\n02/2022')""",
+            "format '%m/%Y' must include %Y, %m and %d",
+        )
+
+    def test_xsv_export(self):
+        with tempfile.TemporaryDirectory() as d:
+            path = os.path.join(d, "out.psv")
+            self.assertEqual(
+                db.execute(
+                    "select xsv_export('select 1 as a, ''x|y'' as b', ?, '|')", [path]
+                ).fetchone()[0],
+                1,
+            )
+            with open(path) as f:
+                self.assertEqual(f.read(), 'a|b\n1|"x|y"\n')
+            self.exec_fails_with(
+                "select xsv_export('select 1', 'out.csv', '')",
+                "a delimiter is required",
+            )
+
+    def test_csv_export(self):
+        execute_all("create table export_students(id, name, score, notes)")
+        execute_all(
+            "insert into export_students values (1, 'alex', 10.5, null), (2, 'brian, jr', 20, x'41')"
+        )
+        with tempfile.TemporaryDirectory() as d:
+            path = os.path.join(d, "students.csv")
+            # a table name exports the whole table
+            self.assertEqual(
+                db.execute("select csv_export('export_students', ?)", [path]).fetchone()[0],
+                2,
+            )
+            with open(path) as f:
+                self.assertEqual(
+                    f.read(),
+                    'id,name,score,notes\n1,alex,10.5,\n2,"brian, jr",20,A\n',
+                )
+
+            db.execute(
+                """select csv_export('select id, notes from export_students', ?, '{"header": false, "null_as": "\\\\N"}')""",
+                [path],
+            ).fetchone()
+            with open(path) as f:
+                self.assertEqual(f.read(), "1,\\N\n2,A\n")
+
+            # compression is chosen from the extension, and the output can be read back
+            for ext in ["gz", "zst"]:
+                path = os.path.join(d, f"students.csv.{ext}")
+                db.execute(
+                    "select csv_export('select id, name from export_students', ?)", [path]
+                ).fetchone()
+                self.assertEqual(
+                    execute_all(
+                        "select row ->> 'name' as name from csv_rows_file(?)", [path]
+                    ),
+                    [{"name": "alex"}, {"name": "brian, jr"}],
+                )
+
+            # errors writing the end of a file, like a full disk, fail the export
+            if os.path.exists("/dev/full"):
+                for ext in ["csv", "csv.gz", "csv.zst"]:
+                    path = os.path.join(d, f"full.{ext}")
+                    os.symlink("/dev/full", path)
+                    self.exec_fails_with(
+                        f"select csv_export('select id, name from export_students', '{path}')",
+                        "Error writing CSV: .*No space left on device",
+                    )
+
+        with tempfile.TemporaryDirectory() as d:
+            path = os.path.join(d, "students.csv")
+            quoted = lambda options: (
+                db.execute(
+                    "select csv_export('select id, name, notes from export_students', ?, ?)",
+                    [path, options],
+                ).fetchone(),
+                open(path, newline="").read(),
+            )[1]
+            self.assertEqual(
+                quoted('{"quote_style": "always"}'),
+                '"id","name","notes"\n"1","alex",""\n"2","brian, jr","A"\n',
+            )
+            self.assertEqual(
+                quoted('{"quote_style": "non_numeric", "header": false}'),
+                '1,"alex",""\n2,"brian, jr","A"\n',
+            )
+            self.assertEqual(
+                quoted('{"quote_style": "never", "header": false, "null_as": "NULL"}'),
+                "1,alex,NULL\n2,brian, jr,A\n",
+            )
+            self.assertEqual(
+                quoted('{"line_terminator": "crlf", "header": false}'),
+                '1,alex,\r\n2,"brian, jr",A\r\n',
+            )
+        self.exec_fails_with(
+            """select csv_export('export_students', 'x.csv', '{"quote_style": "sometimes"}')""",
+            "'quote_style' option must be one of 'necessary', 'always', 'non_numeric', or 'never'",
+        )
+        self.exec_fails_with(
+            """select csv_export('export_students', 'x.csv', '{"line_terminator": "cr"}')""",
+            "'line_terminator' option must be 'lf' or 'crlf'",
+        )
+        self.exec_fails_with(
+            """select csv_export('export_students', 'x.csv', '{"headers": false}')""",
+            "Unknown export option 'headers'",
+        )
+        self.exec_fails_with(
+            "select csv_export('select * from does_not_exist', 'x.csv')",
+            "Error preparing .*: no such table: does_not_exist",
+        )
+        execute_all("drop table export_students")
+
+    def test_tsv_export(self):
+        with tempfile.TemporaryDirectory() as d:
+            path = os.path.join(d, "out.tsv")
+            db.execute("select tsv_export('select 1 as a, 2 as b', ?)", [path]).fetchone()
+            with open(path) as f:
+                self.assertEqual(f.read(), "a\tb\n1\t2\n")
+
+    def test_xsv_import(self):
+        with tempfile.TemporaryDirectory() as d:
+            path = os.path.join(d, "data.psv")
+            with open(path, "w") as f:
+                f.write("a|b\n1|x\n2|y\n")
+            self.assertEqual(
+                db.execute("select xsv_import(?, 'import_psv', '|')", [path]).fetchone()[0],
+                2,
+            )
+            self.assertEqual(
+                execute_all("select * from import_psv"),
+                [{"a": 1, "b": "x"}, {"a": 2, "b": "y"}],
+            )
+        execute_all("drop table import_psv")
+
+    def test_csv_import(self):
+        with tempfile.TemporaryDirectory() as d:
+            path = os.path.join(d, "data.csv")
+            with open(path, "w") as f:
+                f.write("id,score,name,empty\n1,1.5,alex,\n2,,brian,\n3,20,3,\n")
+            self.assertEqual(
+                db.execute(
+                    "select csv_import(?, 'main.import_students', 'batch=2')", [path]
+                ).fetchone()[0],
+                3,
+            )
+            self.assertEqual(
+                execute_all(
+                    "select name, type from pragma_table_info('import_students')"
+                ),
+                [
+                    {"name": "id", "type": "INTEGER"},
+                    {"name": "score", "type": "REAL"},
+                    {"name": "name", "type": "TEXT"},
+                    {"name": "empty", "type": "TEXT"},
+                ],
+            )
+            self.assertEqual(
+                execute_all("select * from import_students"),
+                [
+                    {"id": 1, "score": 1.5, "name": "alex", "empty": ""},
+                    {"id": 2, "score": None, "name": "brian", "empty": ""},
+                    {"id": 3, "score": 20.0, "name": "3", "empty": ""},
+                ],
+            )
+            self.exec_fails_with(
+                "select csv_import('%s', 'import_students')" % path,
+                "table import_students already exists",
+            )
+
+            # types are only inferred from the first infer_types records
+            self.assertEqual(
+                db.execute(
+                    "select csv_import(?, 'import_untyped', '{\"infer_types\": 0, \"header\": false}')",
+                    [path],
+                ).fetchone()[0],
+                4,
+            )
+            self.assertEqual(
+                execute_all(
+                    "select name, type from pragma_table_info('import_untyped')"
+                ),
+                [
+                    {"name": "c1", "type": "TEXT"},
+                    {"name": "c2", "type": "TEXT"},
+                    {"name": "c3", "type": "TEXT"},
+                    {"name": "c4", "type": "TEXT"},
+                ],
+            )
+            self.exec_fails_with(
+                "select csv_import('%s', 'x', 'batch=0')" % path,
+                "'batch' option must be a positive integer",
+            )
+            self.exec_fails_with(
+                "select csv_import('%s', 'x', 'rows=10')" % path,
+                "Unknown import option 'rows'",
+            )
+            self.exec_fails_with(
+                "select csv_import('%s', 'x', 'batch')" % path,
+                "import option 'batch' must be in the form key=value",
+            )
+
+            # "inf" and "NaN" aren't numbers, like with xsv_real()
+            with open(path, "w") as f:
+                f.write("score\n1.5\ninf\n")
+            db.execute("select csv_import(?, 'import_inf')", [path]).fetchone()
+            self.assertEqual(
+                execute_all("select type from pragma_table_info('import_inf')"),
+                [{"type": "TEXT"}],
+            )
+            execute_all("drop table import_inf")
+
+            # leading zeros and "+" signs would be lost as numbers, so stay TEXT
+            with open(path, "w") as f:
+                f.write("zip,phone,id,score\n00501,+15551234,0,0.5\n10001,+15555678,12,-0.25\n")
+            db.execute("select csv_import(?, 'import_zips')", [path]).fetchone()
+            self.assertEqual(
+                execute_all("select type from pragma_table_info('import_zips')"),
+                [{"type": "TEXT"}, {"type": "TEXT"}, {"type": "INTEGER"}, {"type": "REAL"}],
+            )
+            self.assertEqual(
+                execute_all("select zip, phone from import_zips"),
+                [
+                    {"zip": "00501", "phone": "+15551234"},
+                    {"zip": "10001", "phone": "+15555678"},
+                ],
+            )
+            execute_all("drop table import_zips")
+
+            with open(path, "w") as f:
+                f.write("")
+            self.exec_fails_with(
+                "select csv_import('%s', 'import_empty')" % path,
+                "no columns in ",
+            )
+
+            # a file that fails after some batches were inserted leaves no table
+            with open(path, "wb") as f:
+                f.write(b"id,name\n1,alex\n2,brian\n3,craig\n\xff,dana\n")
+            self.exec_fails_with(
+                "select csv_import('%s', 'import_partial', 'batch=1,infer_types=1')" % path,
+                "Error reading",
+            )
+            self.assertEqual(
+                execute_all(
+                    "select count(*) as n from sqlite_master where name = 'import_partial'"
+                ),
+                [{"n": 0}],
+            )
+        execute_all("drop table import_students")
+        execute_all("drop table import_untyped")
+
+    def test_tsv_import(self):
+        self.assertEqual(
+            db.execute(
+                "select tsv_import('tests/data/students.tsv', 'import_tsv')"
+            ).fetchone()[0],
+            3,
+        )
+        self.assertEqual(
+            execute_all("select name, type from pragma_table_info('import_tsv')"),
+            [
+                {"name": "id", "type": "INTEGER"},
+                {"name": "name", "type": "TEXT"},
+                {"name": "age", "type": "INTEGER"},
+                {"name": "process", "type": "REAL"},
+            ],
+        )
+        execute_all("drop table import_tsv")
+
+    def test_csv_export_partitioned(self):
+        execute_all("create table export_sales(id, region, amount)")
+        execute_all(
+            "insert into export_sales values (1, 'EU', 10), (2, 'US', 20), (3, 'EU', 30), (4, 'EU', 40), (5, null, 50)"
+        )
+        with tempfile.TemporaryDirectory() as d:
+            manifest = execute_all(
+                "select path, rows, bytes from csv_export_partitioned('export_sales', ?, '{\"max_rows\": 2}')",
+                [os.path.join(d, "part-{part}.csv")],
+            )
+            self.assertEqual(
+                [(os.path.basename(m["path"]), m["rows"]) for m in manifest],
+                [("part-00001.csv", 2), ("part-00002.csv", 2), ("part-00003.csv", 1)],
+            )
+            for m in manifest:
+                self.assertEqual(m["bytes"], os.path.getsize(m["path"]))
+            with open(os.path.join(d, "part-00003.csv")) as f:
+                self.assertEqual(f.read(), "id,region,amount\n5,,50\n")
+
+            manifest = execute_all(
+                "select path, rows from csv_export_partitioned('select id, region from export_sales', ?, '{\"partition_by\": \"region\"}')",
+                [os.path.join(d, "region={key}", "data-{part}.csv.gz")],
+            )
+            self.assertEqual(
+                [(os.path.relpath(m["path"], d), m["rows"]) for m in manifest],
+                [
+                    (os.path.join("region=EU", "data-00001.csv.gz"), 3),
+                    (os.path.join("region=US", "data-00001.csv.gz"), 1),
+                    (os.path.join("region=__null__", "data-00001.csv.gz"), 1),
+                ],
+            )
+            self.assertEqual(
+                execute_all(
+                    "select row ->> 'id' as id from csv_rows_file(?)",
+                    [os.path.join(d, "region=EU", "data-00001.csv.gz")],
+                ),
+                [{"id": "1"}, {"id": "3"}, {"id": "4"}],
+            )
+
+            # more partitions than files kept open: files closed in between
+            # are appended to, with a single header
+            manifest = execute_all(
+                """
+                select path, rows from csv_export_partitioned(
+                  'with recursive n(value) as (select 0 union all select value + 1 from n where value < 199) select value % 100 as k, value from n',
+                  ?,
+                  '{"partition_by": "k"}'
+                )
+                """,
+                [os.path.join(d, "k={key}", "data.csv.gz")],
+            )
+            self.assertEqual(len(manifest), 100)
+            self.assertEqual({m["rows"] for m in manifest}, {2})
+            self.assertEqual(
+                execute_all(
+                    "select line, row ->> 'value' as value from csv_rows_file(?)",
+                    [os.path.join(d, "k=7", "data.csv.gz")],
+                ),
+                [{"line": 2, "value": "7"}, {"line": 3, "value": "107"}],
+            )
+
+            # values that would share a directory are an error, not merged
+            for query, message in [
+                (
+                    "select 'a/b' as k union all select 'a_b'",
+                    "partition values 'a/b' and 'a_b' both have the key 'a_b'",
+                ),
+                (
+                    "select null as k union all select '__null__'",
+                    "partition values NULL and '__null__' both have the key '__null__'",
+                ),
+            ]:
+                with self.assertRaisesRegex(sqlite3.OperationalError, message):
+                    execute_all(
+                        "select * from csv_export_partitioned(?, ?, '{\"partition_by\": \"k\"}')",
+                        [query, os.path.join(d, "collide", "k={key}", "data.csv")],
+                    )
+
+        # writes files, so can't be used from views
+        execute_all(
+            "create view export_sales_view as select * from csv_export_partitioned('export_sales', 'out-{part}.csv', '{\"max_rows\": 2}')"
+        )
+        self.exec_fails_with(
+            "select * from export_sales_view",
+            "unsafe use of virtual table",
+        )
+        execute_all("drop view export_sales_view")
+
+        self.exec_fails_with(
+            "select * from csv_export_partitioned('export_sales', 'out.csv', '{\"max_rows\": 2}')",
+            "the destination must include \\{part\\}",
+        )
+        self.exec_fails_with(
+            "select * from csv_export_partitioned('export_sales', 'out-{key}.csv', '{\"partition_by\": \"nope\"}')",
+            "partition_by column 'nope' is not a column of the query",
+        )
+        self.exec_fails_with(
+            "select csv_export('export_sales', 'out.csv', '{\"max_rows\": 2}')",
+            "'max_rows' option is only supported by the \\*_export_partitioned functions",
+        )
+        execute_all("drop table export_sales")
+
+    def test_tsv_export_partitioned(self):
+        with tempfile.TemporaryDirectory() as d:
+            # rotated after 10 uncompressed bytes: a 6 byte header and 2 byte rows
+            manifest = execute_all(
+                """
+                select rows from tsv_export_partitioned(
+                  'with recursive n(value) as (select 1 union all select value + 1 from n where value < 5) select value from n',
+                  ?,
+                  '{"max_bytes": 10}'
+                )
+                """,
+                [os.path.join(d, "part-{part}.tsv")],
+            )
+            self.assertEqual(manifest, [{"rows": 2}, {"rows": 2}, {"rows": 1}])
+
+    def test_xsv_export_partitioned(self):
+        with tempfile.TemporaryDirectory() as d:
+            manifest = execute_all(
+                "select path, rows from xsv_export_partitioned('select 1 as a, 2 as b', ?, '|')",
+                [os.path.join(d, "out.psv")],
+            )
+            self.assertEqual(manifest, [{"path": os.path.join(d, "out.psv"), "rows": 1}])
+            with open(os.path.join(d, "out.psv")) as f:
+                self.assertEqual(f.read(), "a|b\n1|2\n")
+
+    def test_xsv_slice(self):
+        xsv_slice = lambda *args: db.execute(
+            "select xsv_slice(row, "
+            + ", ".join("?" * len(args))
+            + ") from csv_rows('a,b,c,d\n1,\"two, 2\",3,4')",
+            args,
+        ).fetchone()[0]
+        self.assertEqual(xsv_slice(0, 2), '1,"two, 2"')
+        self.assertEqual(xsv_slice(2), "3,4")
+        self.assertEqual(xsv_slice(-2, None), "3,4")
+        self.assertEqual(xsv_slice(1, -1), '"two, 2",3')
+        self.assertEqual(xsv_slice(0, 100), '1,"two, 2",3,4')
+        self.assertEqual(xsv_slice(3, 1), "")
+        self.assertEqual(
+            execute_all(
+                "select csv_slice(row, 1) as csv, tsv_slice(row, 0, 1) as tsv from csv_rows('a,b\n1,2')"
+            ),
+            [{"csv": "2", "tsv": "1"}],
+        )
+        self.assertEqual(
+            xsv_slice(0, 2, '{"quote_style": "always"}'), '"1","two, 2"'
+        )
+        # re-serialized with the delimiter of the source
+        self.assertEqual(
+            db.execute(
+                "select xsv_slice(row, 1) from xsv_rows('a|b|c\n1|2|3', '|')"
+            ).fetchone()[0],
+            "2|3",
+        )
+        self.exec_fails_with(
+            "select xsv_slice(row, 'a') from csv_rows('a,b\n1,2')",
+            "xsv_slice\\(\\) bounds must be INTEGER, not TEXT",
+        )
+        self.exec_fails_with(
+            "select xsv_slice('a,b', 0)",
+            "xsv_slice\\(\\) expects a row value from xsv_rows",
+        )
 
     def test_xsv_rows(self):
-        # xsv_rows = lambda *args: execute_all("select * from xsv_rows(?, ?)", args)
-        # self.assertEqual(xsv_rows("name|age\nalex|10\nbrian|20\ncraig|30", "|"), [])
+        xsv_rows = lambda *args: execute_all(
+            "select row ->> 'name' as name, row ->> 'age' as age from xsv_rows(?, ?, ?)",
+            args,
+        )
+        self.assertEqual(
+            xsv_rows("name|age\nalex|10\nbrian|20", "|", None),
+            [{"name": "alex", "age": "10"}, {"name": "brian", "age": "20"}],
+        )
+        self.assertEqual(
+            xsv_rows("name|age\n'alex|a'|10", "|", '{"quote": "\'"}'),
+            [{"name": "alex|a", "age": "10"}],
+        )
 
-        self.skipTest("TODO")
+        csv_rows = lambda *args: execute_all(
+            "select row ->> 0 as first, row ->> 1 as second from csv_rows(?, ?)",
+            args,
+        )
+        # header off: no header row, headers are c1, c2, ...
+        self.assertEqual(
+            execute_all(
+                "select row ->> 'c2' as name from csv_rows(?, ?)",
+                ["1,alex\n2,brian", '{"header": "off"}'],
+            ),
+            [{"name": "alex"}, {"name": "brian"}],
+        )
+        self.assertEqual(
+            execute_all(
+                "select headers ->> 0 as h1, headers ->> 1 as h2 from csv_rows(?, ?) limit 1",
+                ["1,alex\n2,brian", '{"header": false}'],
+            ),
+            [{"h1": "c1", "h2": "c2"}],
+        )
+        self.assertEqual(
+            csv_rows("a,b\n# comment\n1,2", '{"comment": "#"}'),
+            [{"first": "1", "second": "2"}],
+        )
+        self.assertEqual(
+            csv_rows('a,b\n"x\\"y",2', '{"escape": "\\\\"}'),
+            [{"first": 'x"y', "second": "2"}],
+        )
+        self.assertEqual(
+            csv_rows("a,b\n 1 , 2 ", '{"trim": "all"}'),
+            [{"first": "1", "second": "2"}],
+        )
+        self.exec_fails_with(
+            """select * from csv_rows('a,b', '{"what": 1}')""",
+            "Unknown xsv_rows option 'what'",
+        )
+        self.exec_fails_with(
+            """select * from csv_rows('a,b', '{"quote": "ab"}')""",
+            "'quote' option must be a single character string",
+        )
 
     def test_csv_rows(self):
-        self.skipTest("TODO")
+        csv_rows = lambda source: execute_all(
+            "select line, row ->> 'name' as name, row ->> 'age' as age from csv_rows(?)",
+            [source],
+        )
+        expected = [
+            {"line": 2, "name": "alex", "age": "10"},
+            {"line": 3, "name": "brian", "age": "20"},
+        ]
+        self.assertEqual(csv_rows("name,age\nalex,10\nbrian,20"), expected)
+        self.assertEqual(csv_rows(b"name,age\nalex,10\nbrian,20"), expected)
+
+        # compressed BLOBs are decompressed based on their magic bytes
+        for path in ["tests/data/students.csv.gz", "tests/data/students.csv.zst"]:
+            with open(path, "rb") as f:
+                self.assertEqual(
+                    execute_all(
+                        "select row ->> 'name' as name from csv_rows(?)", [f.read()]
+                    ),
+                    [{"name": "alex"}, {"name": "brian"}, {"name": "craig"}],
+                )
+
+        self.exec_fails_with(
+            "select * from csv_rows(1)",
+            "the source of xsv_rows must be a BLOB, TEXT, or reader object",
+        )
+        self.exec_fails_with(
+            "select * from csv_rows(null)", "the source of xsv_rows is NULL"
+        )
 
     def test_tsv_rows(self):
         self.skipTest("TODO")
 
+    def test_xsv_rows_json(self):
+        self.assertEqual(
+            execute_all(
+                "select row_json, row_array from csv_rows('name,age\nalex,10\nbrian,20')"
+            ),
+            [
+                {"row_json": '{"name":"alex","age":"10"}', "row_array": '["alex","10"]'},
+                {"row_json": '{"name":"brian","age":"20"}', "row_array": '["brian","20"]'},
+            ],
+        )
+        # works with SQLite's JSON functions
+        self.assertEqual(
+            execute_all(
+                "select key, value from csv_rows('name,age\nalex,10'), json_each(row_json)"
+            ),
+            [{"key": "name", "value": "alex"}, {"key": "age", "value": "10"}],
+        )
+        # hidden, so not in select *
+        self.assertEqual(
+            list(execute_all("select * from csv_rows('name,age\nalex,10')")[0].keys()),
+            ["row", "headers", "line", "byte", "length"],
+        )
+
+    def test_xsv_to_json(self):
+        self.assertEqual(
+            execute_all(
+                "select xsv_to_json(row) as row, xsv_to_json(headers) as headers from csv_rows('name,age\nalex,10')"
+            ),
+            [{"row": '{"name":"alex","age":"10"}', "headers": '["name","age"]'}],
+        )
+        self.exec_fails_with(
+            "select xsv_to_json('name,age')",
+            "xsv_to_json\\(\\) expects a row or headers value from xsv_rows",
+        )
+
+    def test_xsv_rows_file(self):
+        self.assertEqual(
+            execute_all(
+                "select row ->> 'name' as name from xsv_rows_file('tests/data/students.psv', '|')"
+            ),
+            [{"name": "alex"}, {"name": "brian"}, {"name": "craig"}],
+        )
+
+    def test_csv_rows_file(self):
+        self.assertEqual(
+            execute_all(
+                "select row ->> 'name' as name from csv_rows_file('tests/data/students.csv')"
+            ),
+            [{"name": "alex"}, {"name": "brian"}, {"name": "craig"}],
+        )
+        # compressed files are decompressed like the csv virtual table
+        self.assertEqual(
+            execute_all(
+                "select row ->> 'name' as name from csv_rows_file('tests/data/students.csv.gz')"
+            ),
+            [{"name": "alex"}, {"name": "brian"}, {"name": "craig"}],
+        )
+        self.exec_fails_with(
+            "select * from csv_rows_file(x'00')",
+            "the source of a \\*_rows_file function must be a TEXT path",
+        )
+
+    def test_tsv_rows_file(self):
+        self.assertEqual(
+            execute_all(
+                "select row ->> 'name' as name from tsv_rows_file('tests/data/students.tsv')"
+            ),
+            [{"name": "alex"}, {"name": "brian"}, {"name": "craig"}],
+        )
+
+    def test_xsv_files(self):
+        self.assertEqual(
+            execute_all(
+                "select path, size, compression, delimiter, header from xsv_files('tests/data/student_files/*.csv')"
+            ),
+            [
+                {
+                    "path": f"tests/data/student_files/{name}.csv",
+                    "size": size,
+                    "compression": None,
+                    "delimiter": ",",
+                    "header": '["id","name","age","process"]',
+                }
+                for name, size in [("a", 62), ("b", 61), ("c", 64)]
+            ],
+        )
+        self.assertEqual(
+            execute_all(
+                "select path, compression from xsv_files('tests/data/students.csv*')"
+            ),
+            [
+                {"path": "tests/data/students.csv", "compression": None},
+                {"path": "tests/data/students.csv.gz", "compression": "gzip"},
+                {"path": "tests/data/students.csv.zip", "compression": None},
+                {"path": "tests/data/students.csv.zst", "compression": "zstd"},
+            ],
+        )
+        self.assertEqual(
+            execute_all(
+                "select path, delimiter from xsv_files('tests/data/student_files/a.*', '{\"glob_order\": \"size\"}')"
+            ),
+            [
+                {"path": "tests/data/student_files/a.csv", "delimiter": ","},
+                {"path": "tests/data/student_files/a.psv", "delimiter": "|"},
+                {"path": "tests/data/student_files/a.tsv", "delimiter": "\t"},
+            ],
+        )
+        mtime = execute_all("select mtime from xsv_files('tests/data/students.csv')")[0]["mtime"]
+        self.assertEqual(mtime, int(os.path.getmtime("tests/data/students.csv")))
+
+        # files that can't be read are listed with NULLs instead of failing
+        if not IS_WINDOWS:
+            with tempfile.TemporaryDirectory() as tmp:
+                with open(os.path.join(tmp, "a.csv"), "w") as f:
+                    f.write("id,name\n1,alex\n")
+                os.symlink(os.path.join(tmp, "missing.csv.gz"), os.path.join(tmp, "b.csv.gz"))
+                self.assertEqual(
+                    execute_all(
+                        "select size, mtime is not null as has_mtime, compression, delimiter, header from xsv_files(?)",
+                        [os.path.join(tmp, "*.csv*")],
+                    ),
+                    [
+                        {
+                            "size": 15,
+                            "has_mtime": 1,
+                            "compression": None,
+                            "delimiter": ",",
+                            "header": '["id","name"]',
+                        },
+                        {
+                            "size": None,
+                            "has_mtime": 0,
+                            "compression": None,
+                            "delimiter": None,
+                            "header": None,
+                        },
+                    ],
+                )
+                self.assertEqual(
+                    execute_all(
+                        "select path from xsv_files(?, '{\"skip_unreadable\": true}')",
+                        [os.path.join(tmp, "*.csv*")],
+                    ),
+                    [{"path": os.path.join(tmp, "a.csv")}],
+                )
+                # and sorted last when ordering by modification time
+                with open(os.path.join(tmp, "c.csv"), "w") as f:
+                    f.write("id\n1\n")
+                os.utime(os.path.join(tmp, "a.csv"), (2000000000, 2000000000))
+                os.utime(os.path.join(tmp, "c.csv"), (1000000000, 1000000000))
+                self.assertEqual(
+                    execute_all(
+                        "select path, mtime from xsv_files(?, '{\"glob_order\": \"mtime\"}')",
+                        [os.path.join(tmp, "*.csv*")],
+                    ),
+                    [
+                        {"path": os.path.join(tmp, "c.csv"), "mtime": 1000000000},
+                        {"path": os.path.join(tmp, "a.csv"), "mtime": 2000000000},
+                        {"path": os.path.join(tmp, "b.csv.gz"), "mtime": None},
+                    ],
+                )
+                self.assertEqual(
+                    execute_all(
+                        "select path, size from xsv_files(?, '{\"glob_order\": \"size\"}')",
+                        [os.path.join(tmp, "*.csv*")],
+                    ),
+                    [
+                        {"path": os.path.join(tmp, "c.csv"), "size": 5},
+                        {"path": os.path.join(tmp, "a.csv"), "size": 15},
+                        {"path": os.path.join(tmp, "b.csv.gz"), "size": None},
+                    ],
+                )
+
+        # each matched file can be read individually with a reader table
+        execute_all(
+            "create virtual table temp.files_reader using csv_reader(id integer, name text, age integer, process real)"
+        )
+        self.assertEqual(
+            execute_all(
+                """
+                select files.path, count(*) as rows
+                from xsv_files('tests/data/student_files/*.csv') as files
+                join files_reader(files.path)
+                group by 1
+                """
+            ),
+            [
+                {"path": "tests/data/student_files/a.csv", "rows": 3},
+                {"path": "tests/data/student_files/b.csv", "rows": 3},
+                {"path": "tests/data/student_files/c.csv", "rows": 3},
+            ],
+        )
+
+        self.exec_fails_with(
+            "select * from xsv_files('tests/data/*.csv', '{\"glob_order\": \"random\"}')",
+            "Unknown glob_order value 'random'",
+        )
+        self.exec_fails_with(
+            "select * from xsv_files('tests/data/*.csv', '{\"recursive\": true}')",
+            "Unknown xsv_files option 'recursive'",
+        )
+        self.exec_fails_with(
+            "select * from xsv_files('https://example.com/*.csv')",
+            "xsv_files\\(\\) only lists local files",
+        )
+
     def test_xsv_fields(self):
-        self.skipTest("TODO")
+        self.assertEqual(
+            execute_all(
+                "select line, idx, header, contents from csv_rows('name,age\nalex,10\nbrian,20') join xsv_fields(row)"
+            ),
+            [
+                {"line": 2, "idx": 0, "header": "name", "contents": "alex"},
+                {"line": 2, "idx": 1, "header": "age", "contents": "10"},
+                {"line": 3, "idx": 0, "header": "name", "contents": "brian"},
+                {"line": 3, "idx": 1, "header": "age", "contents": "20"},
+            ],
+        )
+        # the hidden record column re-supplies the input row
+        self.assertEqual(
+            execute_all(
+                "select idx, record ->> 'name' as name, xsv_at(record, -1) as last from csv_rows('name,age\nalex,10') join xsv_fields(row)"
+            ),
+            [
+                {"idx": 0, "name": "alex", "last": "10"},
+                {"idx": 1, "name": "alex", "last": "10"},
+            ],
+        )
 
     def test_csv_fields(self):
-        self.skipTest("TODO")
+        # headers have no header names
+        self.assertEqual(
+            execute_all(
+                "select idx, header, contents from csv_rows('name,age\nalex,10') join csv_fields(headers)"
+            ),
+            [
+                {"idx": 0, "header": None, "contents": "name"},
+                {"idx": 1, "header": None, "contents": "age"},
+            ],
+        )
 
     def test_tsv_fields(self):
-        self.skipTest("TODO")
+        self.assertEqual(
+            execute_all(
+                "select header, contents from tsv_rows('name\tage\nalex\t10') join tsv_fields(row)"
+            ),
+            [
+                {"header": "name", "contents": "alex"},
+                {"header": "age", "contents": "10"},
+            ],
+        )
 
     def test_xsv_line_number(self):
         with self.assertRaisesRegex(
@@ -158,23 +1067,289 @@ class TestXsv(unittest.TestCase):
         ):
             db.execute("select xsv_path(1)")
 
-    def test_csv_path(self):
-        with self.assertRaisesRegex(
-            sqlite3.OperationalError,
-            "unable to use function csv_path in the requested context",
-        ):
-            db.execute("select csv_path(1)")
+    def test_csv_path(self):
+        with self.assertRaisesRegex(
+            sqlite3.OperationalError,
+            "unable to use function csv_path in the requested context",
+        ):
+            db.execute("select csv_path(1)")
+
+    def test_tsv_path(self):
+        with self.assertRaisesRegex(
+            sqlite3.OperationalError,
+            "unable to use function tsv_path in the requested context",
+        ):
+            db.execute("select tsv_path(1)")
+
+    def exec_fails_with(self, sql, message, error=sqlite3.OperationalError):
+        with self.assertRaisesRegex(error, message):
+            execute_all(sql)
+
+    def test_csv_progress(self):
+        db.execute(
+            "create virtual table temp.progress_files using csv(filename='tests/data/student_files/*.csv');"
+        )
+        rows = execute_all(
+            "select name, _bytes_read, _total_bytes, _uncompressed_bytes_read from progress_files"
+        )
+        self.assertEqual(
+            rows[0],
+            {"name": "alex", "_bytes_read": 62, "_total_bytes": 187, "_uncompressed_bytes_read": 33},
+        )
+        self.assertEqual(
+            rows[-1],
+            {"name": "coin", "_bytes_read": 187, "_total_bytes": 187, "_uncompressed_bytes_read": 187},
+        )
+        self.assertEqual(
+            [row["_uncompressed_bytes_read"] for row in rows],
+            sorted(row["_uncompressed_bytes_read"] for row in rows),
+        )
+
+    @unittest.skipIf(IS_WINDOWS, "named pipes are unix-only")
+    def test_csv_stream(self):
+        with tempfile.TemporaryDirectory() as d:
+            path = os.path.join(d, "students.fifo")
+            os.mkfifo(path)
+
+            def write():
+                with open(path, "w") as f:
+                    f.write("id,name\n1,alex\n2,brian\n")
+
+            writer = threading.Thread(target=write)
+            writer.start()
+            # the headers are sniffed from the pipe, then replayed for the scan
+            db.execute(
+                "create virtual table temp.students_fifo using csv(filename='%s')" % path
+            )
+            self.assertEqual(
+                execute_all("select id, name, _total_bytes from temp.students_fifo"),
+                [
+                    {"id": "1", "name": "alex", "_total_bytes": None},
+                    {"id": "2", "name": "brian", "_total_bytes": None},
+                ],
+            )
+            writer.join()
+            self.exec_fails_with(
+                "select * from temp.students_fifo",
+                "is a stream that can only be read once",
+            )
+            execute_all("drop table temp.students_fifo")
+
+        # "-" is stdin
+        script = f"""
+import sqlite3
+db = sqlite3.connect(":memory:")
+db.enable_load_extension(True)
+db.load_extension({EXT_PATH!r})
+db.execute("create virtual table temp.s using csv(filename='-')")
+print(db.execute("select group_concat(name) from temp.s").fetchone()[0])
+"""
+        result = subprocess.run(
+            [sys.executable, "-c", script],
+            input=b"id,name\n1,alex\n2,brian\n",
+            capture_output=True,
+            check=True,
+        )
+        self.assertEqual(result.stdout.decode().strip(), "alex,brian")
+
+    def test_csv_glob_options(self):
+        def first_names(options):
+            db.execute(
+                "create virtual table temp.glob_files using csv(%s)" % options
+            )
+            names = [
+                row["name"]
+                for row in execute_all("select name from temp.glob_files where id = '1'")
+            ]
+            db.execute("drop table temp.glob_files")
+            return names
+
+        files = "filename='tests/data/student_files/*.csv'"
+        self.assertEqual(first_names(files), ["alex", "brian", "craig"])
+        self.assertEqual(first_names(f"{files}, glob_order=name"), ["alex", "brian", "craig"])
+        self.assertEqual(first_names(f"{files}, glob_order=size"), ["brian", "alex", "craig"])
+        self.exec_fails_with(
+            f"create virtual table temp.x using csv({files}, glob_order=random)",
+            "Unknown glob_order value 'random', expected 'name', 'mtime', or 'size'",
+        )
+
+        with tempfile.TemporaryDirectory() as d:
+            for name, mtime in [("a.csv", 300), ("B.CSV", 100), (".c.csv", 200)]:
+                with open(os.path.join(d, name), "w") as f:
+                    f.write(f"id,name\n1,{name}\n")
+                os.utime(os.path.join(d, name), (mtime, mtime))
+            files = f"filename='{d}/*.csv'"
+            self.assertEqual(first_names(files), [".c.csv", "a.csv"])
+            self.assertEqual(
+                first_names(f"{files}, glob_case_insensitive=on"),
+                [".c.csv", "B.CSV", "a.csv"],
+            )
+            self.assertEqual(
+                first_names(f"{files}, glob_case_insensitive=on, glob_order=mtime"),
+                ["B.CSV", ".c.csv", "a.csv"],
+            )
+            self.assertEqual(
+                first_names(f"{files}, glob_case_insensitive=on, glob_hidden=off"),
+                ["B.CSV", "a.csv"],
+            )
 
-    def test_tsv_path(self):
-        with self.assertRaisesRegex(
-            sqlite3.OperationalError,
-            "unable to use function tsv_path in the requested context",
-        ):
-            db.execute("select tsv_path(1)")
+            if not IS_WINDOWS:
+                os.symlink(os.path.join(d, "a.csv"), os.path.join(d, "link.csv"))
+                os.symlink(os.path.join(d, "missing.csv"), os.path.join(d, "dangling.csv"))
+                self.assertEqual(
+                    first_names(f"{files}, skip_unreadable=on"),
+                    [".c.csv", "a.csv", "a.csv"],
+                )
+                self.assertEqual(
+                    first_names(f"{files}, skip_unreadable=on, follow_symlinks=off"),
+                    [".c.csv", "a.csv"],
+                )
+
+    @unittest.skipUnless(
+        "http_support" in db.execute("select xsv_debug()").fetchone()[0],
+        "built without http_support",
+    )
+    def test_csv_http(self):
+        class Handler(http.server.SimpleHTTPRequestHandler):
+            def __init__(self, *args, **kwargs):
+                super().__init__(*args, directory="tests/data", **kwargs)
+
+            def do_GET(self):
+                if self.path != "/encoded.csv":
+                    return super().do_GET()
+                body = gzip.compress(b"id,name\n1,alex\n")
+                self.send_response(200)
+                self.send_header("Content-Encoding", "gzip")
+                self.send_header("Content-Length", str(len(body)))
+                self.end_headers()
+                self.wfile.write(body)
+
+            def log_message(self, *args):
+                pass
+
+        server = http.server.ThreadingHTTPServer(("127.0.0.1", 0), Handler)
+        threading.Thread(target=server.serve_forever, daemon=True).start()
+        base = f"http://127.0.0.1:{server.server_address[1]}"
+        try:
+            for path in ["students.csv", "students.csv.gz", "students.csv.zst"]:
+                execute_all(
+                    f"create virtual table temp.students_http using csv(filename='{base}/{path}')"
+                )
+                self.assertEqual(
+                    execute_all("select name from temp.students_http"),
+                    [{"name": "alex"}, {"name": "brian"}, {"name": "craig"}],
+                )
+                execute_all("drop table temp.students_http")
+            execute_all(
+                f"create virtual table temp.encoded_http using csv(filename='{base}/encoded.csv')"
+            )
+            self.assertEqual(
+                execute_all("select id, name from temp.encoded_http"),
+                [{"id": "1", "name": "alex"}],
+            )
+            execute_all("drop table temp.encoded_http")
+            self.exec_fails_with(
+                f"create virtual table temp.missing_http using csv(filename='{base}/missing.csv')",
+                "Error requesting .*missing.csv",
+            )
+        finally:
+            server.shutdown()
 
-    def exec_fails_with(self, sql, message, error=sqlite3.OperationalError):
-        with self.assertRaisesRegex(error, message):
-            execute_all(sql)
+    @unittest.skipUnless(
+        "s3_support" in db.execute("select xsv_debug()").fetchone()[0],
+        "built without s3_support",
+    )
+    def test_csv_s3(self):
+        objects = {}
+        for name in ["a.csv", "b.csv", "c.csv"]:
+            with open(f"tests/data/student_files/{name}", "rb") as f:
+                objects[f"data/{name}"] = f.read()
+        with open("tests/data/students.csv.gz", "rb") as f:
+            objects["data/students.csv.gz"] = f.read()
+        objects["other/x.csv"] = b"id\n1\n"
+
+        # a stand-in for S3's ListObjectsV2 and ranged GETs, 2 keys per page
+        class Handler(http.server.BaseHTTPRequestHandler):
+            def do_GET(self):
+                if not self.headers.get("Authorization", "").startswith(
+                    "AWS4-HMAC-SHA256 Credential=test-key/"
+                ):
+                    self.send_response(403)
+                    self.end_headers()
+                    return
+                url = urllib.parse.urlparse(self.path)
+                bucket, _, key = url.path.lstrip("/").partition("/")
+                if bucket != "test-bucket":
+                    self.send_response(404)
+                    self.end_headers()
+                    return
+                if not key:
+                    query = urllib.parse.parse_qs(url.query)
+                    prefix = query.get("prefix", [""])[0]
+                    start = int(query.get("continuation-token", ["0"])[0])
+                    keys = sorted(k for k in objects if k.startswith(prefix))
+                    page = keys[start : start + 2]
+                    truncated = start + 2 < len(keys)
+                    body = "<ListBucketResult>"
+                    body += "".join(
+                        f"<Contents><Key>{k}</Key><Size>{len(objects[k])}</Size></Contents>"
+                        for k in page
+                    )
+                    body += f"<IsTruncated>{str(truncated).lower()}</IsTruncated>"
+                    if truncated:
+                        body += f"<NextContinuationToken>{start + 2}</NextContinuationToken>"
+                    body = (body + "</ListBucketResult>").encode()
+                    self.send_response(200)
+                elif key in objects:
+                    first, last = self.headers["Range"].removeprefix("bytes=").split("-")
+                    body = objects[key][int(first) : int(last) + 1]
+                    self.send_response(206)
+                else:
+                    self.send_response(404)
+                    self.end_headers()
+                    return
+                self.send_header("Content-Length", str(len(body)))
+                self.end_headers()
+                self.wfile.write(body)
+
+            def log_message(self, *args):
+                pass
+
+        server = http.server.ThreadingHTTPServer(("127.0.0.1", 0), Handler)
+        threading.Thread(target=server.serve_forever, daemon=True).start()
+        env = {
+            "AWS_ACCESS_KEY_ID": "test-key",
+            "AWS_SECRET_ACCESS_KEY": "test-secret",
+            "AWS_ENDPOINT_URL": f"http://127.0.0.1:{server.server_address[1]}",
+        }
+        previous = {key: os.environ.get(key) for key in env}
+        os.environ.update(env)
+        try:
+            execute_all(
+                "create virtual table temp.students_s3 using csv(filename='s3://test-bucket/data/*.csv')"
+            )
+            self.assertEqual(
+                [row["name"] for row in execute_all("select name from temp.students_s3")],
+                ["alex", "adrian", "andres", "brian", "beto", "brandy", "craig", "catherine", "coin"],
+            )
+            execute_all("drop table temp.students_s3")
+            self.assertEqual(
+                execute_all(
+                    "select row ->> 'name' as name from csv_rows_file('s3://test-bucket/data/students.csv.gz')"
+                ),
+                [{"name": "alex"}, {"name": "brian"}, {"name": "craig"}],
+            )
+            self.exec_fails_with(
+                "select * from csv_rows_file('s3://test-bucket/missing.csv')",
+                "Error requesting s3://test-bucket/missing.csv",
+            )
+        finally:
+            server.shutdown()
+            for key, value in previous.items():
+                if value is None:
+                    del os.environ[key]
+                else:
+                    os.environ[key] = value
 
     def test_csv_gzip(self):
         db.execute(
@@ -479,6 +1654,166 @@ class TestXsv(unittest.TestCase):
             ],
         )
 
+    def test_csv_schema(self):
+        db.execute(
+            "create table main.students_schema(id integer, name text, age integer, process real)"
+        )
+        db.execute(
+            "create virtual table temp.students_with_schema using csv(filename='tests/data/students.csv', schema='main.students_schema');"
+        )
+        self.assertEqual(
+            execute_all("select rowid, * from temp.students_with_schema"),
+            [
+                {"rowid": 1, "id": 1, "name": "alex", "age": 10, "process": 0.9},
+                {"rowid": 2, "id": 2, "name": "brian", "age": 20, "process": 0.7},
+                {"rowid": 3, "id": 3, "name": "craig", "age": 30, "process": 0.3},
+            ],
+        )
+
+    def test_csv_match_columns(self):
+        db.execute(
+            "create virtual table temp.reordered using csv(filename='tests/data/reordered/[ab].csv', match_columns=name, id integer, name text, age integer);"
+        )
+        self.assertEqual(
+            execute_all("select * from temp.reordered"),
+            [
+                {"id": 1, "name": "alex", "age": 10},
+                {"id": 2, "name": "brian", "age": 20},
+                {"id": 3, "name": "craig", "age": 30},
+            ],
+        )
+
+        db.execute(
+            "create virtual table temp.reordered_missing using csv(filename='tests/data/reordered/*.csv', match_columns=name, aliases='id=student_id', id integer, name text, age integer);"
+        )
+        self.exec_fails_with(
+            "select * from temp.reordered_missing",
+            "Column 'age' not found in the headers of .*c.csv",
+        )
+
+        db.execute(
+            "create virtual table temp.reordered_null using csv(filename='tests/data/reordered/*.csv', match_columns=name, missing_columns=null, aliases='id=student_id', id integer, name text, age integer);"
+        )
+        self.assertEqual(
+            execute_all("select * from temp.reordered_null"),
+            [
+                {"id": 1, "name": "alex", "age": 10},
+                {"id": 2, "name": "brian", "age": 20},
+                {"id": 3, "name": "craig", "age": 30},
+                {"id": 4, "name": "dana", "age": None},
+            ],
+        )
+
+        self.exec_fails_with(
+            "create virtual table temp.x using csv(filename='tests/data/reordered/a.csv', match_columns=name, header=off);",
+            "match_columns='name' requires a header row, but header is off",
+        )
+
+    def test_csv_union_by_name(self):
+        db.execute(
+            "create virtual table temp.reordered_union using csv(filename='tests/data/reordered/*.csv', union_by_name=on);"
+        )
+        self.assertEqual(
+            execute_all(
+                "select name, type from pragma_table_info('reordered_union', 'temp')"
+            ),
+            [
+                {"name": "id", "type": ""},
+                {"name": "name", "type": ""},
+                {"name": "age", "type": ""},
+                {"name": "student_id", "type": ""},
+            ],
+        )
+        self.assertEqual(
+            execute_all("select * from temp.reordered_union"),
+            [
+                {"id": "1", "name": "alex", "age": "10", "student_id": None},
+                {"id": "2", "name": "brian", "age": "20", "student_id": None},
+                {"id": "3", "name": "craig", "age": "30", "student_id": None},
+                {"id": None, "name": "dana", "age": None, "student_id": "4"},
+            ],
+        )
+
+        db.execute(
+            "create virtual table temp.reordered_union_aliases using csv(filename='tests/data/reordered/*.csv', union_by_name=on, aliases='id=student_id');"
+        )
+        self.assertEqual(
+            execute_all("select * from temp.reordered_union_aliases"),
+            [
+                {"id": "1", "name": "alex", "age": "10"},
+                {"id": "2", "name": "brian", "age": "20"},
+                {"id": "3", "name": "craig", "age": "30"},
+                {"id": "4", "name": "dana", "age": None},
+            ],
+        )
+
+        self.exec_fails_with(
+            "create virtual table temp.x using csv(filename='tests/data/reordered/*.csv', union_by_name=on, id text);",
+            "union_by_name=on cannot be used alongside column declarations or 'schema'",
+        )
+
+    def test_csv_field_count(self):
+        db.execute(
+            "create virtual table temp.not_enough_flexible using csv(filename='tests/data/not_enough_columns.csv', flexible=on);"
+        )
+        self.assertEqual(
+            execute_all("select * from temp.not_enough_flexible"),
+            [
+                {"a": "1", "b": "2", "c": "3"},
+                {"a": "1", "b": "2", "c": None},
+            ],
+        )
+        db.execute(
+            "create virtual table temp.too_many_flexible using csv(filename='tests/data/too_many_columns.csv', flexible=on);"
+        )
+        self.assertEqual(
+            execute_all("select * from temp.too_many_flexible"),
+            [
+                {"a": "1", "b": "2", "c": "3"},
+                {"a": "1", "b": "2", "c": "3"},
+            ],
+        )
+
+        # by default, columns missing from a file in a glob are NULL, strict=on errors
+        db.execute(
+            'create virtual table temp.g_err_strict using csv(filename="tests/data/glob_error/*.csv", strict=on);'
+        )
+        self.exec_fails_with(
+            "select * from temp.g_err_strict",
+            "Error reading .*c.csv line 2: expected at least 4 fields, found 3",
+        )
+
+        self.exec_fails_with(
+            "create virtual table temp.x using csv(filename='tests/data/students.csv', strict=on, flexible=on);",
+            "strict=on and flexible=on cannot be used together",
+        )
+
+    def test_csv_columns_limit(self):
+        # tests/data/wide.csv has 2005 columns, more than SQLITE_MAX_COLUMN
+        self.exec_fails_with(
+            "create virtual table temp.wide using csv(filename='tests/data/wide.csv');",
+            "tests/data/wide.csv has 2005 columns, more than SQLite's limit of 2000 columns. Use columns_limit=N",
+        )
+        db.execute(
+            "create virtual table temp.wide using csv(filename='tests/data/wide.csv', columns_limit=3);"
+        )
+        self.assertEqual(
+            execute_all("select *, _rest ->> '$[0]' as h4, json_array_length(_rest) as rest_length from temp.wide"),
+            [{"h1": "1", "h2": "2", "h3": "3", "h4": "4", "rest_length": 2002}],
+        )
+        self.exec_fails_with(
+            "create virtual table temp.x using csv(filename='tests/data/wide.csv', columns_limit=2000);",
+            "columns_limit must be between 1 and 1999",
+        )
+        # a limit wider than the file declares every column, with an empty _rest
+        db.execute(
+            "create virtual table temp.narrow using csv(filename='tests/data/students.csv', columns_limit=10);"
+        )
+        self.assertEqual(
+            execute_all("select id, process, _rest from temp.narrow limit 1"),
+            [{"id": "1", "process": ".9", "_rest": "[]"}],
+        )
+
     def test_xsv_inferred_name(self):
         db.execute(
             "create virtual table temp.\"tests/data/students.psv\" using xsv(delimiter='|');"
@@ -576,6 +1911,9 @@ class TestXsv(unittest.TestCase):
                 {"cid": 1, "name": "name", "type": "", "hidden": 0},
                 {"cid": 2, "name": "age", "type": "", "hidden": 0},
                 {"cid": 3, "name": "process", "type": "", "hidden": 0},
+                {"cid": 4, "name": "_bytes_read", "type": "", "hidden": 1},
+                {"cid": 5, "name": "_total_bytes", "type": "", "hidden": 1},
+                {"cid": 6, "name": "_uncompressed_bytes_read", "type": "", "hidden": 1},
             ],
         )
 
@@ -591,7 +1929,7 @@ class TestXsv(unittest.TestCase):
         )
         with self.assertRaisesRegex(
             sqlite3.OperationalError,
-            "Error while reading next row: CSV error: .* found record with 2 fields, but the previous record has 3 fields",
+            "Error reading .*not_enough_columns.csv line 3: expected 3 fields, found 2. Use flexible=on",
         ):
             execute_all("select * from not_enough_columns")
 
@@ -607,7 +1945,7 @@ class TestXsv(unittest.TestCase):
         )
         with self.assertRaisesRegex(
             sqlite3.OperationalError,
-            "Error while reading next row: CSV error: .* found record with 4 fields, but the previous record has 3 fields",
+            "Error reading .*too_many_columns.csv line 3: expected 3 fields, found 4. Use flexible=on",
         ):
             execute_all("select * from too_many_columns")
 
@@ -652,6 +1990,150 @@ class TestXsv(unittest.TestCase):
         #  "Error: no file extension detected for 'what'"
         # )
 
+    def test_csv_reader_column_selectors(self):
+        reader_columns = lambda name: [
+            (row["name"], row["type"].lower())
+            for row in execute_all(f"select name, type from pragma_table_info('{name}')")
+        ]
+        execute_all(
+            "create virtual table temp.selector_all using csv_reader(sniff='tests/data/students.csv', *)"
+        )
+        self.assertEqual(
+            reader_columns("selector_all"),
+            [("id", ""), ("name", ""), ("age", ""), ("process", "")],
+        )
+        execute_all(
+            "create virtual table temp.selector_exclude using csv_reader(sniff='tests/data/students.csv', * exclude (process) replace (id integer, age integer))"
+        )
+        self.assertEqual(
+            reader_columns("selector_exclude"),
+            [("id", "integer"), ("name", ""), ("age", "integer")],
+        )
+        self.assertEqual(
+            execute_all(
+                "select * from selector_exclude('tests/data/students.csv') limit 1"
+            ),
+            [{"id": 1, "name": "alex", "age": 10}],
+        )
+        execute_all(
+            "create virtual table temp.selector_regex using csv_reader(sniff='tests/data/students.csv', name text, columns('^(age|process)$') real)"
+        )
+        self.assertEqual(
+            reader_columns("selector_regex"),
+            [("name", "text"), ("age", "real"), ("process", "real")],
+        )
+        self.assertEqual(
+            execute_all("select * from selector_regex('tests/data/students.csv') limit 1"),
+            [{"name": "alex", "age": 10.0, "process": 0.9}],
+        )
+        # excluding a middle column, fields are still matched by header name
+        execute_all(
+            "create virtual table temp.selector_exclude_middle using csv_reader(sniff='tests/data/students.csv', * exclude (name))"
+        )
+        self.assertEqual(
+            execute_all(
+                "select * from selector_exclude_middle('tests/data/students.csv') limit 1"
+            ),
+            [{"id": "1", "age": "10", "process": ".9"}],
+        )
+        # sources with reordered headers are matched by name too
+        self.assertEqual(
+            execute_all(
+                "select * from selector_exclude_middle(cast('process,age,name,id\n.5,20,brian,2' as blob))"
+            ),
+            [{"id": "2", "age": "20", "process": ".5"}],
+        )
+
+        self.exec_fails_with(
+            "create virtual table temp.x using csv_reader(*)",
+            "column selectors like '\\*' require a sample file with headers, like sniff='data.csv'",
+        )
+        self.exec_fails_with(
+            "create virtual table temp.x using csv_reader(sniff='tests/data/students.csv', * exclude (nope))",
+            "Column 'nope' not found in the headers of tests/data/students.csv",
+        )
+        self.exec_fails_with(
+            "create virtual table temp.x using csv_reader(sniff='tests/data/students.csv', columns('^zzz'))",
+            "columns\\('\\^zzz'\\) matched no headers of tests/data/students.csv",
+        )
+        self.exec_fails_with(
+            "create virtual table temp.x using csv_reader(sniff='tests/data/students.csv', header=off, *)",
+            "column selectors like '\\*' require a header row, but header is off",
+        )
+        self.exec_fails_with(
+            "create virtual table temp.x using csv_reader(sniff='tests/data/students.csv', * rename (id))",
+            "Unknown column selector modifier 'rename'",
+        )
+
+    def test_csv_reader_provenance(self):
+        execute_all(
+            "create virtual table temp.provenance_reader using csv_reader(id integer, name text);"
+        )
+        self.assertEqual(
+            execute_all(
+                "select name, _line, _byte, _length, _path from provenance_reader('tests/data/students.csv')"
+            ),
+            [
+                {"name": "alex", "_line": 2, "_byte": 20, "_length": 4, "_path": "tests/data/students.csv"},
+                {"name": "brian", "_line": 3, "_byte": 33, "_length": 4, "_path": "tests/data/students.csv"},
+                {"name": "craig", "_line": 4, "_byte": 47, "_length": 4, "_path": "tests/data/students.csv"},
+            ],
+        )
+        # hidden, so not in select *
+        self.assertEqual(
+            execute_all("select * from provenance_reader('tests/data/students.csv') limit 1"),
+            [{"id": 1, "name": "alex"}],
+        )
+        self.assertEqual(
+            execute_all(
+                "select _line, _path from provenance_reader(cast('id,name\n1,alex' as blob))"
+            ),
+            [{"_line": 2, "_path": None}],
+        )
+
+    def test_csv_reader_progress(self):
+        execute_all(
+            "create virtual table temp.progress_reader using csv_reader(id integer, name text);"
+        )
+        self.assertEqual(
+            execute_all(
+                "select name, _bytes_read, _total_bytes, _uncompressed_bytes_read from progress_reader('tests/data/students.csv.gz')"
+            ),
+            [
+                {"name": "alex", "_bytes_read": 91, "_total_bytes": 91, "_uncompressed_bytes_read": 33},
+                {"name": "brian", "_bytes_read": 91, "_total_bytes": 91, "_uncompressed_bytes_read": 47},
+                {"name": "craig", "_bytes_read": 91, "_total_bytes": 91, "_uncompressed_bytes_read": 61},
+            ],
+        )
+        self.assertEqual(
+            execute_all(
+                "select _bytes_read, _total_bytes from progress_reader(cast('id,name\n1,alex' as blob))"
+            ),
+            [{"_bytes_read": 14, "_total_bytes": 14}],
+        )
+
+    def test_csv_reader_compressed_blob(self):
+        execute_all(
+            "create virtual table students_blob_reader using csv_reader(id integer, name, age integer, progess real);"
+        )
+        for path in ["tests/data/students.csv.gz", "tests/data/students.csv.zst"]:
+            with open(path, "rb") as f:
+                self.assertEqual(
+                    execute_all(
+                        "select name, age from students_blob_reader(?)", [f.read()]
+                    ),
+                    [
+                        {"name": "alex", "age": 10},
+                        {"name": "brian", "age": 20},
+                        {"name": "craig", "age": 30},
+                    ],
+                )
+        # reader objects are NULL-typed pointers, a plain NULL is an error
+        self.exec_fails_with(
+            "select * from students_blob_reader(null)",
+            "the source of xsv_reader must be a path, BLOB, or reader object, not NULL",
+        )
+
     def test_csv_reader(self):
         # now with affinity!
         execute_all(
@@ -755,6 +2237,95 @@ class TestXsv(unittest.TestCase):
                 ],
             )
 
+    def test_csv_reader_schema(self):
+        db.execute(
+            "create table reader_schema(id integer, name text, age integer, progess real)"
+        )
+        db.execute(
+            "create virtual table students_reader_schema using csv_reader(schema='reader_schema');"
+        )
+        self.assertEqual(
+            execute_all(
+                "select * from students_reader_schema('tests/data/student_files/a.csv')"
+            ),
+            [
+                {"id": 1, "name": "alex", "age": 10, "progess": 0.9},
+                {"id": 2, "name": "adrian", "age": 20, "progess": 0.8},
+                {"id": 3, "name": "andres", "age": 30, "progess": 0.7},
+            ],
+        )
+        self.exec_fails_with(
+            "create virtual table x using csv_reader(schema='not_exist');",
+            "schema table 'not_exist' does not exist or has no columns",
+        )
+        self.exec_fails_with(
+            "create virtual table x using csv_reader(schema='reader_schema', id text);",
+            "'schema' cannot be used alongside column declarations",
+        )
+
+    def test_csv_reader_match_columns(self):
+        db.execute(
+            "create virtual table reordered_reader using csv_reader(match_columns=name, missing_columns=null, aliases='id=student_id', id integer, name text, age integer);"
+        )
+        self.assertEqual(
+            execute_all(
+                """
+                select students.*
+                from json_each('["tests/data/reordered/a.csv", "tests/data/reordered/b.csv", "tests/data/reordered/c.csv"]') as files
+                join reordered_reader(files.value) as students
+                """
+            ),
+            [
+                {"id": 1, "name": "alex", "age": 10},
+                {"id": 2, "name": "brian", "age": 20},
+                {"id": 3, "name": "craig", "age": 30},
+                {"id": 4, "name": "dana", "age": None},
+            ],
+        )
+
+    def test_csv_reader_field_count(self):
+        db.execute(
+            "create virtual table field_count_reader using csv_reader(a, b, c);"
+        )
+        self.exec_fails_with(
+            "select * from field_count_reader('tests/data/not_enough_columns.csv')",
+            "Error reading tests/data/not_enough_columns.csv line 3: expected 3 fields, found 2",
+        )
+        self.exec_fails_with(
+            "select * from field_count_reader(cast('a,b,c\n1,2,3\n1,2' as blob))",
+            "Error reading BLOB line 3: expected 3 fields, found 2",
+        )
+        # like xsv, declared columns past the end of every record are NULL by default
+        db.execute(
+            "create virtual table field_count_reader_wide using csv_reader(a, b, c, d, e);"
+        )
+        self.assertEqual(
+            execute_all(
+                "select d, e from field_count_reader_wide('tests/data/students_no_header.csv') limit 1"
+            ),
+            [{"d": ".7", "e": None}],
+        )
+        db.execute(
+            "create virtual table field_count_reader_strict using csv_reader(strict=on, a, b, c, d, e);"
+        )
+        self.exec_fails_with(
+            "select * from field_count_reader_strict('tests/data/students_no_header.csv')",
+            "Error reading tests/data/students_no_header.csv line 2: expected at least 5 fields, found 4",
+        )
+
+        db.execute(
+            "create virtual table field_count_reader_flexible using csv_reader(flexible=on, a, b, c);"
+        )
+        self.assertEqual(
+            execute_all(
+                "select * from field_count_reader_flexible('tests/data/not_enough_columns.csv')"
+            ),
+            [
+                {"a": "1", "b": "2", "c": "3"},
+                {"a": "1", "b": "2", "c": None},
+            ],
+        )
+
     def test_tsv_reader(self):
         execute_all(
             "create virtual table students_tsv_reader using tsv_reader(id integer primary key, name text, age integer, progess real);"